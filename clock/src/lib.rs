@@ -0,0 +1,243 @@
+use std::fmt;
+
+use instant::Instant;
+use winit::keyboard::{Key, NamedKey};
+
+/// Simulation time driven by the wall clock.
+///
+/// Supports pausing, single-stepping, time scaling and a fixed-timestep
+/// mode. Wall-clock deltas are clamped, so a stall (e.g. while the window
+/// is dragged) does not make the animation jump.
+pub struct Clock {
+    last: Instant,
+    time: f32,
+    frame_time: f32,
+    accumulator: f32,
+    step_requested: bool,
+    pub paused: bool,
+    pub time_scale: f32,
+    /// Longest wall-clock delta that is applied at once, in seconds
+    pub max_delta: f32,
+    /// If set, the simulation time advances in multiples of this step
+    pub fixed_timestep: Option<f32>,
+}
+
+impl Clock {
+    /// Length of a single step while paused or in the fixed-timestep mode
+    pub const DEFAULT_STEP: f32 = 1.0 / 60.0;
+
+    pub fn new() -> Self {
+        Self {
+            last: Instant::now(),
+            time: 0.0,
+            frame_time: 0.0,
+            accumulator: 0.0,
+            step_requested: false,
+            paused: false,
+            time_scale: 1.0,
+            max_delta: 0.25,
+            fixed_timestep: None,
+        }
+    }
+
+    /// Simulation time in seconds.
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn set_time(&mut self, time: f32) {
+        self.time = time;
+        self.accumulator = 0.0;
+    }
+
+    /// Unscaled and unclamped wall-clock duration of the last frame, in seconds.
+    pub fn frame_time(&self) -> f32 {
+        self.frame_time
+    }
+
+    /// Advances the clock by the wall-clock time since the previous tick and
+    /// returns the simulation delta.
+    pub fn tick(&mut self) -> f32 {
+        let now = Instant::now();
+        let frame_time = now.duration_since(self.last).as_secs_f32();
+        self.last = now;
+        self.advance(frame_time)
+    }
+
+    /// Advances the clock by `frame_time` seconds of wall-clock time and
+    /// returns the simulation delta, like [`Clock::tick`].
+    pub fn advance(&mut self, frame_time: f32) -> f32 {
+        self.frame_time = frame_time;
+
+        let delta = if self.paused {
+            if std::mem::take(&mut self.step_requested) {
+                self.fixed_timestep.unwrap_or(Self::DEFAULT_STEP)
+            } else {
+                0.0
+            }
+        } else {
+            self.frame_time.min(self.max_delta) * self.time_scale
+        };
+
+        let delta = match self.fixed_timestep {
+            Some(step) => {
+                self.accumulator += delta;
+                let steps = (self.accumulator / step).floor();
+                self.accumulator -= steps * step;
+                steps * step
+            }
+            None => delta,
+        };
+        self.time += delta;
+        delta
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    /// Advances by a single step on the next tick; pauses the clock first.
+    pub fn step(&mut self) {
+        self.paused = true;
+        self.step_requested = true;
+    }
+
+    pub fn toggle_fixed_timestep(&mut self) {
+        self.fixed_timestep = match self.fixed_timestep {
+            Some(_) => None,
+            None => Some(Self::DEFAULT_STEP),
+        };
+        self.accumulator = 0.0;
+    }
+
+    /// Handles the clock key bindings and returns `true` if `key` was one of them.
+    ///
+    /// - Space: pause / resume
+    /// - `.`: single step
+    /// - `[` / `]`: halve / double the time scale
+    /// - `=`: reset the time scale
+    /// - `t`: toggle the fixed timestep
+    pub fn handle_key(&mut self, key: &Key) -> bool {
+        match key {
+            Key::Named(NamedKey::Space) => self.toggle_pause(),
+            Key::Character(c) => match c.as_str() {
+                "." => self.step(),
+                "[" => self.time_scale = (self.time_scale * 0.5).max(1.0 / 64.0),
+                "]" => self.time_scale = (self.time_scale * 2.0).min(64.0),
+                "=" => self.time_scale = 1.0,
+                "t" => self.toggle_fixed_timestep(),
+                _ => return false,
+            },
+            _ => return false,
+        }
+        log::info!("clock: {}", self);
+        true
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for Clock {
+    /// Describes the settings, e.g. `0.5x, paused, fixed 60 Hz`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x", self.time_scale)?;
+        if self.paused {
+            write!(f, ", paused")?;
+        }
+        if let Some(step) = self.fixed_timestep {
+            write!(f, ", fixed {} Hz", (1.0 / step).round())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEP: f32 = Clock::DEFAULT_STEP;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn clamps_and_scales_the_delta() {
+        let mut clock = Clock::new();
+        assert_close(clock.advance(0.1), 0.1);
+        assert_close(clock.advance(2.0), 0.25);
+        assert_close(clock.frame_time(), 2.0);
+
+        clock.time_scale = 0.5;
+        assert_close(clock.advance(2.0), 0.125);
+        assert_close(clock.time(), 0.475);
+    }
+
+    #[test]
+    fn pause_stops_and_step_advances_once() {
+        let mut clock = Clock::new();
+        clock.toggle_pause();
+        assert_eq!(clock.advance(0.1), 0.0);
+
+        clock.step();
+        assert_close(clock.advance(0.1), STEP);
+        assert_eq!(clock.advance(0.1), 0.0);
+        assert!(clock.paused);
+        assert_close(clock.time(), STEP);
+    }
+
+    #[test]
+    fn step_pauses_and_uses_the_fixed_timestep() {
+        let mut clock = Clock::new();
+        clock.fixed_timestep = Some(0.5);
+        clock.step();
+        assert!(clock.paused);
+        assert_close(clock.advance(0.1), 0.5);
+    }
+
+    #[test]
+    fn fixed_timestep_carries_the_remainder_over() {
+        let mut clock = Clock::new();
+        clock.toggle_fixed_timestep();
+        assert_eq!(clock.advance(STEP * 0.6), 0.0);
+        assert_close(clock.advance(STEP * 0.6), STEP);
+        assert_close(clock.advance(STEP * 2.0), STEP * 2.0);
+        assert_close(clock.advance(STEP * 0.8), STEP);
+        assert_close(clock.time(), STEP * 4.0);
+
+        // Toggling drops the remainder
+        clock.toggle_fixed_timestep();
+        clock.toggle_fixed_timestep();
+        assert_eq!(clock.advance(STEP * 0.9), 0.0);
+    }
+
+    #[test]
+    fn set_time_drops_the_remainder() {
+        let mut clock = Clock::new();
+        clock.fixed_timestep = Some(STEP);
+        clock.advance(STEP * 0.9);
+        clock.set_time(10.0);
+        assert_eq!(clock.advance(STEP * 0.5), 0.0);
+        assert_eq!(clock.time(), 10.0);
+    }
+
+    #[test]
+    fn handles_the_key_bindings() {
+        let mut clock = Clock::new();
+        assert!(clock.handle_key(&Key::Character("]".into())));
+        assert_eq!(clock.time_scale, 2.0);
+        assert!(clock.handle_key(&Key::Named(NamedKey::Space)));
+        assert!(clock.handle_key(&Key::Character("t".into())));
+        assert_eq!(clock.to_string(), "2x, paused, fixed 60 Hz");
+        assert!(!clock.handle_key(&Key::Character("x".into())));
+    }
+}
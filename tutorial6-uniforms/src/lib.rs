@@ -1,5 +1,5 @@
+use clock::Clock;
 use glam::{vec3, Mat4};
-use instant::Instant;
 use wgpu::util::DeviceExt;
 use winit::{
     event::*,
@@ -29,8 +29,7 @@ struct State<'w> {
 
     size: winit::dpi::PhysicalSize<u32>,
     window: &'w Window,
-    time: f32,
-    instant: Instant,
+    clock: Clock,
 }
 
 #[repr(C)]
@@ -349,8 +348,7 @@ impl<'w> State<'w> {
             num_indices,
             size,
             window,
-            time: 0.0,
-            instant: Instant::now(),
+            clock: Clock::new(),
         }
     }
 
@@ -366,17 +364,17 @@ impl<'w> State<'w> {
     }
 
     fn update(&mut self) {
-        self.time += self.instant.elapsed().as_secs_f32();
-        self.instant = Instant::now();
+        self.clock.tick();
+        let time = self.clock.time();
 
-        let local = Mat4::from_rotation_x(self.time * 7.);
+        let local = Mat4::from_rotation_x(time * 7.);
         let view = {
-            let d = (self.time * 3.).cos() * 0.5 + 1.2;
+            let d = (time * 3.).cos() * 0.5 + 1.2;
             glam::Mat4::look_at_lh(
                 vec3(
-                    (self.time * 3.).cos() * d,
-                    (self.time * 5.).sin() * 0.5,
-                    (self.time * 3.).sin() * d,
+                    (time * 3.).cos() * d,
+                    (time * 5.).sin() * 0.5,
+                    (time * 3.).sin() * d,
                 ),
                 vec3(0., 0., 0.),
                 vec3(0., 1., 0.),
@@ -468,6 +466,19 @@ pub async fn run(event_loop: event_loop::EventLoop<()>, window: Window) {
                     } => {
                         target.exit();
                     }
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                state: ElementState::Pressed,
+                                logical_key,
+                                ..
+                            },
+                        ..
+                    } if state.clock.handle_key(&logical_key) => {
+                        state
+                            .window
+                            .set_title(&format!("winit window - {}", state.clock));
+                    }
                     WindowEvent::RedrawRequested => {
                         state.update();
                         match state.render() {
@@ -1,5 +1,5 @@
+use clock::Clock;
 use glam::{vec3, Mat4, Quat, Vec3};
-use instant::Instant;
 use rand::seq::SliceRandom;
 use wgpu::util::DeviceExt;
 use winit::{
@@ -32,8 +32,7 @@ struct State<'w> {
 
     size: winit::dpi::PhysicalSize<u32>,
     window: &'w Window,
-    instant: Instant,
-    time: f32,
+    clock: Clock,
     value_d: f32,
 }
 
@@ -419,8 +418,7 @@ impl<'w> State<'w> {
             num_indices,
             size,
             window,
-            time: 0.0,
-            instant: Instant::now(),
+            clock: Clock::new(),
             value_d: 10.0,
         }
     }
@@ -437,14 +435,14 @@ impl<'w> State<'w> {
     }
 
     fn update(&mut self) {
-        self.time += self.instant.elapsed().as_secs_f32();
-        self.instant = Instant::now();
+        self.clock.tick();
+        let time = self.clock.time();
 
         {
             let raws: Vec<_> = self
                 .instances
                 .iter()
-                .map(|inst| inst.to_raw(self.time))
+                .map(|inst| inst.to_raw(time))
                 .collect();
             self.queue
                 .write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&raws))
@@ -452,8 +450,8 @@ impl<'w> State<'w> {
 
         let view = {
             let d = self.value_d.max(0.1);
-            let theta = self.time * 0.1;
-            let phi = ((self.time * 0.7).cos() + 1.0) * std::f32::consts::PI / 8.0;
+            let theta = time * 0.1;
+            let phi = ((time * 0.7).cos() + 1.0) * std::f32::consts::PI / 8.0;
             glam::Mat4::look_at_lh(
                 vec3(
                     theta.cos() * phi.cos() * d,
@@ -542,6 +540,19 @@ pub async fn run(event_loop: event_loop::EventLoop<()>, window: Window) {
                     WindowEvent::CloseRequested => {
                         target.exit();
                     }
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                state: ElementState::Pressed,
+                                logical_key,
+                                ..
+                            },
+                        ..
+                    } if state.clock.handle_key(&logical_key) => {
+                        state
+                            .window
+                            .set_title(&format!("winit window - {}", state.clock));
+                    }
                     WindowEvent::MouseWheel { delta, .. } => match delta {
                         MouseScrollDelta::PixelDelta(pos) => {
                             state.value_d += (pos.y / 20.0) as f32;
@@ -0,0 +1,232 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::fmt::Write;
+
+use crate::Options;
+
+/// Adapter chosen with `--adapter` or `WGPU_ADAPTER_NAME`, by its position
+/// in `--list-adapters` or by a part of its name.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AdapterSelector {
+    Index(usize),
+    Name(String),
+}
+
+impl AdapterSelector {
+    pub fn parse(value: &str) -> Self {
+        match value.parse() {
+            Ok(index) => Self::Index(index),
+            Err(_) => Self::Name(value.to_lowercase()),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn matches(&self, index: usize, info: &wgpu::AdapterInfo) -> bool {
+        match self {
+            Self::Index(i) => *i == index,
+            Self::Name(name) => info.name.to_lowercase().contains(name),
+        }
+    }
+}
+
+/// Parses a comma-separated list like `vulkan,gl`.
+pub fn parse_backends(value: &str) -> Result<wgpu::Backends, String> {
+    let mut backends = wgpu::Backends::empty();
+    for name in value.split(',') {
+        backends |= match name.trim().to_ascii_lowercase().as_str() {
+            "vulkan" | "vk" => wgpu::Backends::VULKAN,
+            "metal" | "mtl" => wgpu::Backends::METAL,
+            "dx12" | "d3d12" => wgpu::Backends::DX12,
+            "gl" | "gles" | "opengl" => wgpu::Backends::GL,
+            "webgpu" => wgpu::Backends::BROWSER_WEBGPU,
+            "primary" => wgpu::Backends::PRIMARY,
+            "secondary" => wgpu::Backends::SECONDARY,
+            "all" => wgpu::Backends::all(),
+            _ => return Err(format!("unknown backend '{}'", name)),
+        };
+    }
+    Ok(backends)
+}
+
+pub fn parse_power_preference(value: &str) -> Result<wgpu::PowerPreference, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "low" => Ok(wgpu::PowerPreference::LowPower),
+        "high" => Ok(wgpu::PowerPreference::HighPerformance),
+        "none" => Ok(wgpu::PowerPreference::None),
+        _ => Err(format!("unknown power preference '{}'", value)),
+    }
+}
+
+/// Backends from the command line, `WGPU_BACKEND` or all of them.
+pub fn backends(options: &Options) -> wgpu::Backends {
+    options
+        .backends
+        .or_else(wgpu::util::backend_bits_from_env)
+        .unwrap_or(wgpu::Backends::all())
+}
+
+fn power_preference(options: &Options) -> wgpu::PowerPreference {
+    options
+        .power_preference
+        .or_else(wgpu::util::power_preference_from_env)
+        .unwrap_or_default()
+}
+
+fn selector(options: &Options) -> Option<AdapterSelector> {
+    options.adapter.clone().or_else(|| {
+        std::env::var("WGPU_ADAPTER_NAME")
+            .ok()
+            .map(|name| AdapterSelector::parse(&name))
+    })
+}
+
+/// Picks the selected adapter, or the one wgpu prefers for the power
+/// preference that can present to `surface` if there is one.
+pub async fn select(
+    instance: &wgpu::Instance,
+    surface: Option<&wgpu::Surface<'_>>,
+    options: &Options,
+) -> Option<wgpu::Adapter> {
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(selector) = selector(options) {
+        let adapter = instance
+            .enumerate_adapters(backends(options))
+            .into_iter()
+            .enumerate()
+            .find(|(index, adapter)| selector.matches(*index, &adapter.get_info()))
+            .map(|(_, adapter)| adapter);
+        return match adapter {
+            Some(adapter)
+                if surface.is_none_or(|surface| adapter.is_surface_supported(surface)) =>
+            {
+                Some(adapter)
+            }
+            Some(adapter) => {
+                log::error!("{} cannot present to the window", adapter.get_info().name);
+                None
+            }
+            None => {
+                log::error!("no adapter matches {:?}, see --list-adapters", selector);
+                None
+            }
+        };
+    }
+    #[cfg(target_arch = "wasm32")]
+    if selector(options).is_some() {
+        log::warn!("adapters cannot be selected on the web");
+    }
+
+    instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: power_preference(options),
+            force_fallback_adapter: options.force_fallback_adapter,
+            compatible_surface: surface,
+        })
+        .await
+}
+
+/// Describes the adapters of the selected backends with their features and
+/// limits.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn report(options: &Options) -> String {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: backends(options),
+        ..Default::default()
+    });
+    let adapters = instance.enumerate_adapters(backends(options));
+    if adapters.is_empty() {
+        return "no adapters found".to_string();
+    }
+
+    let mut report = String::new();
+    for (index, adapter) in adapters.iter().enumerate() {
+        let info = adapter.get_info();
+        let _ = write!(
+            report,
+            "{}: {} ({:?}, {:?})\n\
+             \x20  vendor {:#06x}, device {:#06x}, driver {} {}\n\
+             \x20  features: {:?}\n\
+             \x20  downlevel: {:?}\n\
+             \x20  limits: {}\n",
+            index,
+            info.name,
+            info.backend,
+            info.device_type,
+            info.vendor,
+            info.device,
+            info.driver,
+            info.driver_info,
+            adapter.features(),
+            adapter.get_downlevel_capabilities().flags,
+            format!("{:#?}", adapter.limits()).replace('\n', "\n   "),
+        );
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_backend_lists() {
+        assert_eq!(parse_backends("vulkan"), Ok(wgpu::Backends::VULKAN));
+        assert_eq!(
+            parse_backends("VK, gles,dx12"),
+            Ok(wgpu::Backends::VULKAN | wgpu::Backends::GL | wgpu::Backends::DX12)
+        );
+        assert_eq!(parse_backends("all"), Ok(wgpu::Backends::all()));
+    }
+
+    #[test]
+    fn rejects_unknown_backends() {
+        assert_eq!(
+            parse_backends("vulkan,glide"),
+            Err("unknown backend 'glide'".to_string())
+        );
+        assert_eq!(parse_backends(""), Err("unknown backend ''".to_string()));
+    }
+
+    #[test]
+    fn parses_power_preferences() {
+        assert_eq!(
+            parse_power_preference("High"),
+            Ok(wgpu::PowerPreference::HighPerformance)
+        );
+        assert_eq!(
+            parse_power_preference("max"),
+            Err("unknown power preference 'max'".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_adapter_selectors() {
+        assert_eq!(AdapterSelector::parse("1"), AdapterSelector::Index(1));
+        assert_eq!(
+            AdapterSelector::parse("GeForce"),
+            AdapterSelector::Name("geforce".to_string())
+        );
+        // Negative numbers are names
+        assert_eq!(
+            AdapterSelector::parse("-1"),
+            AdapterSelector::Name("-1".to_string())
+        );
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn matches_by_index_or_name() {
+        let info = wgpu::AdapterInfo {
+            name: "NVIDIA GeForce RTX".to_string(),
+            vendor: 0,
+            device: 0,
+            device_type: wgpu::DeviceType::DiscreteGpu,
+            driver: String::new(),
+            driver_info: String::new(),
+            backend: wgpu::Backend::Vulkan,
+        };
+        assert!(AdapterSelector::parse("1").matches(1, &info));
+        assert!(!AdapterSelector::parse("0").matches(1, &info));
+        assert!(AdapterSelector::parse("geforce rtx").matches(0, &info));
+        assert!(!AdapterSelector::parse("radeon").matches(0, &info));
+    }
+}
@@ -0,0 +1,78 @@
+use crate::settings::DepthPrepass;
+
+/// Compares the frame time without and with a depth pre-pass.
+///
+/// Renders a number of frames with the pre-pass off and then with `mode`,
+/// and logs the mean frame times. The comparison is only meaningful with an
+/// uncapped frame rate and a present mode that does not wait for vertical
+/// sync, see the "v" and "m" keys.
+pub struct PrepassBenchmark {
+    mode: DepthPrepass,
+    /// Setting restored when done
+    restore: DepthPrepass,
+    frames: usize,
+    /// Frame times in seconds without and with the pre-pass
+    runs: [Vec<f32>; 2],
+    warm_up: usize,
+}
+
+impl PrepassBenchmark {
+    /// Frames skipped after switching, while the pipelines settle
+    const WARM_UP: usize = 10;
+
+    pub fn new(mode: DepthPrepass, restore: DepthPrepass, frames: usize) -> Self {
+        Self {
+            mode,
+            restore,
+            frames,
+            runs: [Vec::new(), Vec::new()],
+            warm_up: Self::WARM_UP,
+        }
+    }
+
+    /// Records the time of a frame and returns the pre-pass setting for the
+    /// next one, or `None` once both runs are complete.
+    pub fn record(&mut self, frame_time: f32) -> Option<DepthPrepass> {
+        if self.warm_up > 0 {
+            self.warm_up -= 1;
+            return Some(self.current());
+        }
+        let run = usize::from(self.runs[0].len() == self.frames);
+        self.runs[run].push(frame_time);
+        if self.runs[1].len() == self.frames {
+            self.report();
+            return None;
+        }
+        if run == 0 && self.runs[0].len() == self.frames {
+            self.warm_up = Self::WARM_UP;
+        }
+        Some(self.current())
+    }
+
+    /// Setting to restore after the benchmark.
+    pub fn restore(&self) -> DepthPrepass {
+        self.restore
+    }
+
+    fn current(&self) -> DepthPrepass {
+        if self.runs[0].len() < self.frames {
+            DepthPrepass::Off
+        } else {
+            self.mode
+        }
+    }
+
+    fn report(&self) {
+        let mean = |times: &[f32]| times.iter().sum::<f32>() / times.len() as f32 * 1000.0;
+        let without = mean(&self.runs[0]);
+        let with = mean(&self.runs[1]);
+        log::info!(
+            "depth pre-pass {:?}: {:.3} ms without, {:.3} ms with ({:+.1}%) over {} frames",
+            self.mode,
+            without,
+            with,
+            (with / without - 1.0) * 100.0,
+            self.frames
+        );
+    }
+}
@@ -0,0 +1,47 @@
+// Bright pass, separable blur and composite of the bloom effect
+
+struct BloomParams {
+    threshold: f32,
+    intensity: f32,
+    // Blur direction in texels
+    direction: vec2<f32>,
+}
+
+@group(1) @binding(0)
+var<uniform> params: BloomParams;
+@group(1) @binding(1)
+var bloom_texture: texture_2d<f32>;
+
+// Downsamples with a box filter and keeps what exceeds the threshold
+@fragment
+fn fs_extract(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let offset = texel_size() * 0.5;
+    let color = (
+        textureSample(input_texture, input_sampler, in.uv + vec2<f32>(-offset.x, -offset.y)).rgb +
+        textureSample(input_texture, input_sampler, in.uv + vec2<f32>(offset.x, -offset.y)).rgb +
+        textureSample(input_texture, input_sampler, in.uv + vec2<f32>(-offset.x, offset.y)).rgb +
+        textureSample(input_texture, input_sampler, in.uv + vec2<f32>(offset.x, offset.y)).rgb
+    ) * 0.25;
+    let brightness = max(color.r, max(color.g, color.b));
+    let contribution = max(brightness - params.threshold, 0.0) / max(brightness, 1e-4);
+    return vec4<f32>(color * contribution, 1.0);
+}
+
+// 9-tap gaussian using linear filtering between texels
+@fragment
+fn fs_blur(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let step = params.direction * texel_size();
+    var color = textureSample(input_texture, input_sampler, in.uv).rgb * 0.2270270270;
+    color += textureSample(input_texture, input_sampler, in.uv + step * 1.3846153846).rgb * 0.3162162162;
+    color += textureSample(input_texture, input_sampler, in.uv - step * 1.3846153846).rgb * 0.3162162162;
+    color += textureSample(input_texture, input_sampler, in.uv + step * 3.2307692308).rgb * 0.0702702703;
+    color += textureSample(input_texture, input_sampler, in.uv - step * 3.2307692308).rgb * 0.0702702703;
+    return vec4<f32>(color, 1.0);
+}
+
+@fragment
+fn fs_composite(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(input_texture, input_sampler, in.uv);
+    let bloom = textureSample(bloom_texture, input_sampler, in.uv).rgb;
+    return vec4<f32>(color.rgb + bloom * params.intensity, color.a);
+}
@@ -0,0 +1,20 @@
+// Color grading through a 3D lookup table indexed by sRGB-encoded color
+
+@group(1) @binding(1)
+var lut_texture: texture_3d<f32>;
+
+fn linear_to_srgb(linear: vec3<f32>) -> vec3<f32> {
+    let low = linear * 12.92;
+    let high = 1.055 * pow(linear, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, linear <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(input_texture, input_sampler, in.uv);
+    let size = f32(textureDimensions(lut_texture).x);
+    let encoded = linear_to_srgb(clamp(color.rgb, vec3<f32>(0.0), vec3<f32>(1.0)));
+    // Sample at texel centers, the LUT is stored as sRGB and decoded by the sampler
+    let coord = encoded * ((size - 1.0) / size) + 0.5 / size;
+    return vec4<f32>(textureSample(lut_texture, input_sampler, coord).rgb, color.a);
+}
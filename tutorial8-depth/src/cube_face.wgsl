@@ -0,0 +1,57 @@
+// Full-screen triangle rendering one face of a cube map
+
+struct FaceParams {
+    face: u32,
+    roughness: f32,
+    // Width of the first mip level of the source
+    source_size: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: FaceParams;
+
+const PI: f32 = 3.14159265359;
+
+struct FaceOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Face coordinates in -1..1, t pointing down
+    @location(0) st: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> FaceOutput {
+    var out: FaceOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let clip = uv * 2.0 - 1.0;
+    out.clip_position = vec4<f32>(clip, 0.0, 1.0);
+    out.st = vec2<f32>(clip.x, -clip.y);
+    return out;
+}
+
+// Direction through a texel of the face, in the order +X, -X, +Y, -Y, +Z, -Z
+fn face_direction(face: u32, st: vec2<f32>) -> vec3<f32> {
+    let s = st.x;
+    let t = st.y;
+    var direction: vec3<f32>;
+    switch face {
+        case 0u: {
+            direction = vec3<f32>(1.0, -t, -s);
+        }
+        case 1u: {
+            direction = vec3<f32>(-1.0, -t, s);
+        }
+        case 2u: {
+            direction = vec3<f32>(s, 1.0, t);
+        }
+        case 3u: {
+            direction = vec3<f32>(s, -1.0, -t);
+        }
+        case 4u: {
+            direction = vec3<f32>(s, -t, 1.0);
+        }
+        default: {
+            direction = vec3<f32>(-s, -t, -1.0);
+        }
+    }
+    return normalize(direction);
+}
@@ -0,0 +1,33 @@
+// Projects an equirectangular panorama onto the faces of a cube map
+
+// Float32 textures are not filterable everywhere, so they are read texel by texel
+@group(1) @binding(0)
+var panorama: texture_2d<f32>;
+
+fn load_wrapped(texel: vec2<i32>, size: vec2<i32>) -> vec4<f32> {
+    let x = (texel.x % size.x + size.x) % size.x;
+    let y = clamp(texel.y, 0, size.y - 1);
+    return textureLoad(panorama, vec2<i32>(x, y), 0);
+}
+
+@fragment
+fn fs_main(in: FaceOutput) -> @location(0) vec4<f32> {
+    let direction = face_direction(params.face, in.st);
+    let uv = vec2<f32>(
+        atan2(direction.x, direction.z) / (2.0 * PI) + 0.5,
+        acos(clamp(direction.y, -1.0, 1.0)) / PI,
+    );
+
+    // Bilinear filtering, wrapping around horizontally
+    let size = vec2<i32>(textureDimensions(panorama));
+    let position = uv * vec2<f32>(size) - 0.5;
+    let texel = vec2<i32>(floor(position));
+    let f = fract(position);
+    let top = mix(load_wrapped(texel, size), load_wrapped(texel + vec2<i32>(1, 0), size), f.x);
+    let bottom = mix(
+        load_wrapped(texel + vec2<i32>(0, 1), size),
+        load_wrapped(texel + vec2<i32>(1, 1), size),
+        f.x,
+    );
+    return vec4<f32>(mix(top, bottom, f.y).rgb, 1.0);
+}
@@ -0,0 +1,110 @@
+use std::fmt;
+
+/// Failures that stop the tutorial, with what to try in their messages.
+#[derive(Debug)]
+pub enum Error {
+    EventLoop(winit::error::EventLoopError),
+    Window(winit::error::OsError),
+    Surface(wgpu::CreateSurfaceError),
+    /// No adapter matched the options or could present to the window
+    NoAdapter,
+    Device(wgpu::RequestDeviceError),
+    Image(image::ImageError),
+    OutOfMemory,
+    #[cfg(not(target_arch = "wasm32"))]
+    Recording(std::io::Error),
+    #[cfg(target_arch = "wasm32")]
+    Logger(log::SetLoggerError),
+    /// The page has no element to put the canvas in
+    #[cfg(target_arch = "wasm32")]
+    Canvas,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::EventLoop(err) => write!(f, "cannot create the event loop: {}", err),
+            Self::Window(err) => write!(f, "cannot create the window: {}", err),
+            Self::Surface(err) => write!(f, "cannot create a surface for the window: {}", err),
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::NoAdapter => write!(
+                f,
+                "no compatible adapter for this surface, see --list-adapters, or try \
+                 --backend gl or --fallback-adapter"
+            ),
+            #[cfg(target_arch = "wasm32")]
+            Self::NoAdapter => write!(
+                f,
+                "no compatible adapter for this surface, the browser may not support WebGL 2"
+            ),
+            Self::Device(err) => write!(
+                f,
+                "cannot open the device: {}, try another adapter with --adapter",
+                err
+            ),
+            Self::Image(err) => write!(f, "cannot load the texture: {}", err),
+            Self::OutOfMemory => write!(
+                f,
+                "the GPU ran out of memory, try a smaller grid or window, or no MSAA"
+            ),
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Recording(err) => write!(f, "recording failed: {}", err),
+            #[cfg(target_arch = "wasm32")]
+            Self::Logger(err) => write!(f, "cannot send the logs to the console: {}", err),
+            #[cfg(target_arch = "wasm32")]
+            Self::Canvas => write!(
+                f,
+                "cannot add the canvas to the page, it needs an element with the id 'wasm-example'"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::EventLoop(err) => Some(err),
+            Self::Window(err) => Some(err),
+            Self::Surface(err) => Some(err),
+            Self::Device(err) => Some(err),
+            Self::Image(err) => Some(err),
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Recording(err) => Some(err),
+            #[cfg(target_arch = "wasm32")]
+            Self::Logger(err) => Some(err),
+            #[cfg(target_arch = "wasm32")]
+            Self::Canvas => None,
+            Self::NoAdapter | Self::OutOfMemory => None,
+        }
+    }
+}
+
+impl From<winit::error::EventLoopError> for Error {
+    fn from(err: winit::error::EventLoopError) -> Self {
+        Self::EventLoop(err)
+    }
+}
+
+impl From<winit::error::OsError> for Error {
+    fn from(err: winit::error::OsError) -> Self {
+        Self::Window(err)
+    }
+}
+
+impl From<wgpu::CreateSurfaceError> for Error {
+    fn from(err: wgpu::CreateSurfaceError) -> Self {
+        Self::Surface(err)
+    }
+}
+
+impl From<wgpu::RequestDeviceError> for Error {
+    fn from(err: wgpu::RequestDeviceError) -> Self {
+        Self::Device(err)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(err: image::ImageError) -> Self {
+        Self::Image(err)
+    }
+}
@@ -0,0 +1,48 @@
+// Fast approximate anti-aliasing, after the simplified FXAA by Timothy Lottes
+
+const REDUCE_MIN: f32 = 1.0 / 128.0;
+const REDUCE_MUL: f32 = 1.0 / 8.0;
+const SPAN_MAX: f32 = 8.0;
+
+// Luma of the perceptually encoded color
+fn luma(color: vec3<f32>) -> f32 {
+    return dot(sqrt(color), vec3<f32>(0.299, 0.587, 0.114));
+}
+
+fn sample_rgb(uv: vec2<f32>) -> vec3<f32> {
+    return textureSample(input_texture, input_sampler, uv).rgb;
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let texel = texel_size();
+    let color = textureSample(input_texture, input_sampler, in.uv);
+    let luma_nw = luma(sample_rgb(in.uv + vec2<f32>(-1.0, -1.0) * texel));
+    let luma_ne = luma(sample_rgb(in.uv + vec2<f32>(1.0, -1.0) * texel));
+    let luma_sw = luma(sample_rgb(in.uv + vec2<f32>(-1.0, 1.0) * texel));
+    let luma_se = luma(sample_rgb(in.uv + vec2<f32>(1.0, 1.0) * texel));
+    let luma_m = luma(color.rgb);
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    // Blur along the edge, perpendicular to the luma gradient
+    var direction = vec2<f32>(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+    let scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * scale, vec2<f32>(-SPAN_MAX), vec2<f32>(SPAN_MAX)) * texel;
+
+    let rgb_a = 0.5 * (
+        sample_rgb(in.uv + direction * (1.0 / 3.0 - 0.5)) +
+        sample_rgb(in.uv + direction * (2.0 / 3.0 - 0.5))
+    );
+    let rgb_b = rgb_a * 0.5 + 0.25 * (
+        sample_rgb(in.uv - direction * 0.5) +
+        sample_rgb(in.uv + direction * 0.5)
+    );
+    let luma_b = luma(rgb_b);
+    let rgb = select(rgb_b, rgb_a, luma_b < luma_min || luma_b > luma_max);
+    return vec4<f32>(rgb, color.a);
+}
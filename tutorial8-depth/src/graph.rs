@@ -0,0 +1,440 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt::Write;
+
+/// Texture allocated by the graph for the frame, at the size of the
+/// [`TransientPool`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextureDesc {
+    pub format: wgpu::TextureFormat,
+    pub sample_count: u32,
+    pub usage: wgpu::TextureUsages,
+}
+
+/// Handle of a resource declared in a [`RenderGraph`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Resource(usize);
+
+enum ResourceKind<'a> {
+    Transient(TextureDesc),
+    /// Owned outside the graph, only tracked for the ordering
+    Imported(Option<&'a wgpu::TextureView>),
+}
+
+struct ResourceEntry<'a> {
+    name: &'static str,
+    kind: ResourceKind<'a>,
+}
+
+type Execute<'a> = Box<dyn FnOnce(&mut wgpu::CommandEncoder, &PassResources) + 'a>;
+
+struct Pass<'a> {
+    name: &'static str,
+    reads: Vec<Resource>,
+    writes: Vec<Resource>,
+    execute: Execute<'a>,
+}
+
+/// The passes of a frame with the resources they read and write.
+///
+/// The passes are ordered by their dependencies: for each resource, the
+/// passes only writing it (e.g. clearing it) come first, then the passes
+/// reading and writing it (e.g. blending onto it), and the passes only
+/// reading it last. Otherwise the passes keep the order they were added in.
+///
+/// Transient textures are taken from a [`TransientPool`] for the passes
+/// between their first and last use, so textures with the same description
+/// are shared by resources that are not used at the same time.
+///
+/// Only the passes drawing a frame go through the graph. One-off work such
+/// as the prefiltering in [`crate::ibl`] and the buffer uploads of the UI
+/// record into their own encoders.
+#[derive(Default)]
+pub struct RenderGraph<'a> {
+    resources: Vec<ResourceEntry<'a>>,
+    passes: Vec<Pass<'a>>,
+}
+
+/// Views of the resources, for the passes being executed.
+pub struct PassResources<'r> {
+    views: Vec<Option<&'r wgpu::TextureView>>,
+}
+
+impl PassResources<'_> {
+    /// View of a transient texture or of an imported one.
+    pub fn view(&self, resource: Resource) -> &wgpu::TextureView {
+        self.views[resource.0].expect("Resource should have a view")
+    }
+}
+
+/// Execution order of the passes and the pool slot of each transient texture.
+struct Schedule {
+    order: Vec<usize>,
+    slots: Vec<Option<usize>>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn add_resource(&mut self, name: &'static str, kind: ResourceKind<'a>) -> Resource {
+        self.resources.push(ResourceEntry { name, kind });
+        Resource(self.resources.len() - 1)
+    }
+
+    pub fn transient(&mut self, name: &'static str, desc: TextureDesc) -> Resource {
+        self.add_resource(name, ResourceKind::Transient(desc))
+    }
+
+    /// A resource the graph only orders the passes by, like a buffer.
+    pub fn import(&mut self, name: &'static str) -> Resource {
+        self.add_resource(name, ResourceKind::Imported(None))
+    }
+
+    pub fn import_view(&mut self, name: &'static str, view: &'a wgpu::TextureView) -> Resource {
+        self.add_resource(name, ResourceKind::Imported(Some(view)))
+    }
+
+    pub fn add_pass(
+        &mut self,
+        name: &'static str,
+        reads: &[Resource],
+        writes: &[Resource],
+        execute: impl FnOnce(&mut wgpu::CommandEncoder, &PassResources) + 'a,
+    ) {
+        self.passes.push(Pass {
+            name,
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            execute: Box::new(execute),
+        });
+    }
+
+    /// Orders the passes topologically, panicking on a cycle.
+    fn order(&self) -> Vec<usize> {
+        let mut dependencies = vec![Vec::new(); self.passes.len()];
+        for resource in 0..self.resources.len() {
+            let resource = Resource(resource);
+            // Writing only, then reading and writing, then reading only
+            let mut users: Vec<(u8, usize)> = self
+                .passes
+                .iter()
+                .enumerate()
+                .filter_map(|(i, pass)| {
+                    let reads = pass.reads.contains(&resource);
+                    match (pass.writes.contains(&resource), reads) {
+                        (true, false) => Some((0, i)),
+                        (true, true) => Some((1, i)),
+                        (false, true) => Some((2, i)),
+                        (false, false) => None,
+                    }
+                })
+                .collect();
+            users.sort_unstable();
+            let mut last_writer = None;
+            for (rank, pass) in users {
+                if let Some(writer) = last_writer {
+                    dependencies[pass].push(writer);
+                }
+                if rank < 2 {
+                    last_writer = Some(pass);
+                }
+            }
+        }
+
+        let mut remaining: Vec<usize> = dependencies.iter().map(Vec::len).collect();
+        let mut dependents = vec![Vec::new(); self.passes.len()];
+        for (pass, dependencies) in dependencies.iter().enumerate() {
+            for &dependency in dependencies {
+                dependents[dependency].push(pass);
+            }
+        }
+        let mut ready: BinaryHeap<Reverse<usize>> = (0..self.passes.len())
+            .filter(|&pass| remaining[pass] == 0)
+            .map(Reverse)
+            .collect();
+        let mut order = Vec::with_capacity(self.passes.len());
+        while let Some(Reverse(pass)) = ready.pop() {
+            order.push(pass);
+            for &dependent in &dependents[pass] {
+                remaining[dependent] -= 1;
+                if remaining[dependent] == 0 {
+                    ready.push(Reverse(dependent));
+                }
+            }
+        }
+        if order.len() < self.passes.len() {
+            let cycle: Vec<&str> = (0..self.passes.len())
+                .filter(|&pass| remaining[pass] > 0)
+                .map(|pass| self.passes[pass].name)
+                .collect();
+            panic!("render graph has a cycle between {:?}", cycle);
+        }
+        order
+    }
+
+    fn schedule(&self) -> Schedule {
+        let order = self.order();
+
+        let mut last_use = vec![None; self.resources.len()];
+        for (position, &pass) in order.iter().enumerate() {
+            let pass = &self.passes[pass];
+            for resource in pass.reads.iter().chain(&pass.writes) {
+                last_use[resource.0] = Some(position);
+            }
+        }
+
+        // Slots per description, taken at the first use of a texture and
+        // given back after its last one
+        let mut slots = vec![None; self.resources.len()];
+        let mut free: HashMap<TextureDesc, Vec<usize>> = HashMap::new();
+        let mut allocated: HashMap<TextureDesc, usize> = HashMap::new();
+        for (position, &pass) in order.iter().enumerate() {
+            let pass = &self.passes[pass];
+            let used = || pass.reads.iter().chain(&pass.writes);
+            for resource in used() {
+                let ResourceKind::Transient(desc) = self.resources[resource.0].kind else {
+                    continue;
+                };
+                if slots[resource.0].is_none() {
+                    let slot = free.entry(desc).or_default().pop().unwrap_or_else(|| {
+                        let count = allocated.entry(desc).or_default();
+                        *count += 1;
+                        *count - 1
+                    });
+                    slots[resource.0] = Some(slot);
+                }
+            }
+            for resource in used() {
+                if let ResourceKind::Transient(desc) = self.resources[resource.0].kind {
+                    let slot = slots[resource.0].unwrap();
+                    let slots_free = free.entry(desc).or_default();
+                    if last_use[resource.0] == Some(position) && !slots_free.contains(&slot) {
+                        slots_free.push(slot);
+                    }
+                }
+            }
+        }
+
+        Schedule { order, slots }
+    }
+
+    /// Encodes the passes in their order.
+    pub fn execute(
+        self,
+        device: &wgpu::Device,
+        pool: &mut TransientPool,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let schedule = self.schedule();
+        for (entry, slot) in self.resources.iter().zip(&schedule.slots) {
+            if let (ResourceKind::Transient(desc), Some(slot)) = (&entry.kind, slot) {
+                pool.allocate(device, *desc, *slot);
+            }
+        }
+
+        let resources = PassResources {
+            views: self
+                .resources
+                .iter()
+                .zip(&schedule.slots)
+                .map(|(entry, slot)| match entry.kind {
+                    ResourceKind::Transient(desc) => slot.map(|slot| pool.view(desc, slot)),
+                    ResourceKind::Imported(view) => view,
+                })
+                .collect(),
+        };
+        let mut passes: Vec<Option<Pass>> = self.passes.into_iter().map(Some).collect();
+        for pass in schedule.order {
+            let pass = passes[pass].take().unwrap();
+            (pass.execute)(encoder, &resources);
+        }
+    }
+
+    /// Describes the ordered passes and the transient textures.
+    pub fn dump(&self) -> String {
+        let schedule = self.schedule();
+        let names = |resources: &[Resource]| {
+            resources
+                .iter()
+                .map(|resource| self.resources[resource.0].name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let mut dump = String::from("render graph:\n");
+        for (position, &pass) in schedule.order.iter().enumerate() {
+            let pass = &self.passes[pass];
+            let _ = writeln!(
+                dump,
+                "  {:2} {:<20} reads [{}] writes [{}]",
+                position,
+                pass.name,
+                names(&pass.reads),
+                names(&pass.writes)
+            );
+        }
+        for (entry, slot) in self.resources.iter().zip(&schedule.slots) {
+            if let ResourceKind::Transient(desc) = entry.kind {
+                let slot = slot.map_or("unused".to_string(), |slot| format!("slot {}", slot));
+                let _ = writeln!(
+                    dump,
+                    "  transient {:<20} {:?} x{} {}",
+                    entry.name, desc.format, desc.sample_count, slot
+                );
+            }
+        }
+        dump
+    }
+}
+
+/// Textures of the transient resources, kept across frames until the size
+/// changes.
+pub struct TransientPool {
+    width: u32,
+    height: u32,
+    textures: HashMap<TextureDesc, Vec<wgpu::TextureView>>,
+}
+
+impl TransientPool {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            textures: HashMap::new(),
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.textures.clear();
+    }
+
+    fn allocate(&mut self, device: &wgpu::Device, desc: TextureDesc, slot: usize) {
+        let textures = self.textures.entry(desc).or_default();
+        while textures.len() <= slot {
+            let label = format!(
+                "transient {:?} x{} {}",
+                desc.format,
+                desc.sample_count,
+                textures.len()
+            );
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(&label),
+                size: wgpu::Extent3d {
+                    width: self.width.max(1),
+                    height: self.height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                dimension: wgpu::TextureDimension::D2,
+                mip_level_count: 1,
+                sample_count: desc.sample_count,
+                format: desc.format,
+                usage: desc.usage,
+                view_formats: &[],
+            });
+            textures.push(texture.create_view(&wgpu::TextureViewDescriptor::default()));
+        }
+    }
+
+    fn view(&self, desc: TextureDesc, slot: usize) -> &wgpu::TextureView {
+        &self.textures[&desc][slot]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLOR: TextureDesc = TextureDesc {
+        format: wgpu::TextureFormat::Rgba16Float,
+        sample_count: 1,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+    };
+    const DEPTH: TextureDesc = TextureDesc {
+        format: wgpu::TextureFormat::Depth32Float,
+        sample_count: 1,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+    };
+
+    fn names(graph: &RenderGraph, order: &[usize]) -> Vec<&'static str> {
+        order.iter().map(|&pass| graph.passes[pass].name).collect()
+    }
+
+    #[test]
+    fn orders_writers_before_readers() {
+        let mut graph = RenderGraph::new();
+        let color = graph.transient("color", COLOR);
+        let surface = graph.import("surface");
+        graph.add_pass("post", &[color], &[surface], |_, _| {});
+        graph.add_pass("blend", &[color], &[color], |_, _| {});
+        graph.add_pass("clear", &[], &[color], |_, _| {});
+
+        assert_eq!(names(&graph, &graph.order()), ["clear", "blend", "post"]);
+    }
+
+    #[test]
+    fn keeps_independent_passes_in_order() {
+        let mut graph = RenderGraph::new();
+        let a = graph.import("a");
+        let b = graph.import("b");
+        graph.add_pass("first", &[], &[a], |_, _| {});
+        graph.add_pass("second", &[], &[b], |_, _| {});
+        graph.add_pass("third", &[], &[a], |_, _| {});
+
+        assert_eq!(names(&graph, &graph.order()), ["first", "second", "third"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "cycle")]
+    fn panics_on_a_cycle() {
+        let mut graph = RenderGraph::new();
+        let a = graph.import("a");
+        let b = graph.import("b");
+        graph.add_pass("ab", &[a], &[b], |_, _| {});
+        graph.add_pass("ba", &[b], &[a], |_, _| {});
+
+        graph.order();
+    }
+
+    #[test]
+    fn shares_slots_between_textures_not_used_together() {
+        let mut graph = RenderGraph::new();
+        let surface = graph.import("surface");
+        let depth = graph.transient("depth", DEPTH);
+        let overdraw_depth = graph.transient("overdraw depth", DEPTH);
+        let color = graph.transient("color", COLOR);
+        let bloom = graph.transient("bloom", COLOR);
+        graph.add_pass("scene", &[], &[color, depth], |_, _| {});
+        graph.add_pass("bloom", &[color], &[bloom], |_, _| {});
+        graph.add_pass("composite", &[color, bloom], &[surface], |_, _| {});
+        graph.add_pass(
+            "overdraw",
+            &[surface],
+            &[overdraw_depth, surface],
+            |_, _| {},
+        );
+
+        let slots = graph.schedule().slots;
+        assert_eq!(slots[depth.0], Some(0));
+        assert_eq!(slots[overdraw_depth.0], Some(0));
+        // Both are read by the composite
+        assert_eq!(slots[color.0], Some(0));
+        assert_eq!(slots[bloom.0], Some(1));
+        assert_eq!(slots[surface.0], None);
+    }
+
+    #[test]
+    fn leaves_unused_textures_without_a_slot() {
+        let mut graph = RenderGraph::new();
+        let used = graph.transient("used", COLOR);
+        let unused = graph.transient("unused", COLOR);
+        graph.add_pass("draw", &[], &[used], |_, _| {});
+
+        let slots = graph.schedule().slots;
+        assert_eq!(slots[used.0], Some(0));
+        assert_eq!(slots[unused.0], None);
+    }
+}
@@ -0,0 +1,89 @@
+use glam::Vec3;
+
+use crate::text::TextRenderer;
+use clock::Clock;
+
+/// Values shown on the HUD besides the frame time.
+pub struct HudInfo<'a> {
+    pub clock: &'a Clock,
+    pub present_mode: wgpu::PresentMode,
+    pub frame_latency: u32,
+    pub max_fps: Option<u32>,
+    pub instances: usize,
+    pub distance: f32,
+    pub theta: f32,
+    pub phi: f32,
+    pub eye: Vec3,
+    /// `x` and `z` of the picked instance
+    pub picked: Option<(i32, i32)>,
+}
+
+/// Frame time, instance count and camera parameters drawn in the top-left
+/// corner of the window.
+pub struct Hud {
+    pub visible: bool,
+    /// Exponential moving average of the frame time, in seconds
+    frame_time: f32,
+}
+
+impl Hud {
+    const MARGIN: f32 = 8.0;
+    const COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.9];
+
+    pub fn new() -> Self {
+        Self {
+            visible: true,
+            frame_time: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.frame_time = if self.frame_time == 0.0 {
+            dt
+        } else {
+            self.frame_time * 0.95 + dt * 0.05
+        };
+    }
+
+    pub fn queue(&self, text: &mut TextRenderer, info: &HudInfo) {
+        if !self.visible {
+            return;
+        }
+
+        let fps = if self.frame_time > 0.0 {
+            1.0 / self.frame_time
+        } else {
+            0.0
+        };
+        let clock = info.clock;
+        let mut lines = format!(
+            "frame {:6.2} ms ({:5.1} fps)\n{:?}  latency {}  cap {}\ntime {:.2} s  x{}{}{}\ninstances {}\ndistance {:.1}  theta {:.1} deg  phi {:.1} deg\neye ({:.1}, {:.1}, {:.1})",
+            self.frame_time * 1000.0,
+            fps,
+            info.present_mode,
+            info.frame_latency,
+            match info.max_fps {
+                Some(max_fps) => format!("{} fps", max_fps),
+                None => "off".to_string(),
+            },
+            clock.time(),
+            clock.time_scale,
+            if clock.paused { "  paused" } else { "" },
+            match clock.fixed_timestep {
+                Some(step) => format!("  fixed {:.0} Hz", 1.0 / step),
+                None => String::new(),
+            },
+            info.instances,
+            info.distance,
+            info.theta.to_degrees().rem_euclid(360.0),
+            info.phi.to_degrees(),
+            info.eye.x,
+            info.eye.y,
+            info.eye.z,
+        );
+        if let Some((x, z)) = info.picked {
+            lines += &format!("\npicked x {} z {}", x, z);
+        }
+        text.queue(&lines, [Self::MARGIN, Self::MARGIN], Self::COLOR);
+    }
+}
@@ -0,0 +1,550 @@
+use std::path::Path;
+
+use image::Rgba32FImage;
+use wgpu::util::DeviceExt;
+
+use crate::skybox::CubeFaces;
+
+/// Loads a Radiance HDR or OpenEXR equirectangular panorama.
+pub fn load_equirect(path: &Path) -> Result<Rgba32FImage, String> {
+    match image::open(path) {
+        Ok(image) => Ok(image.to_rgba32f()),
+        Err(err) => Err(format!("{}: {}", path.display(), err)),
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct FaceParams {
+    face: u32,
+    roughness: f32,
+    source_size: f32,
+    _padding: u32,
+}
+
+/// Pipelines rendering the faces of the environment cube maps.
+struct Prefilter {
+    params_layout: wgpu::BindGroupLayout,
+    cube_layout: wgpu::BindGroupLayout,
+    equirect_layout: wgpu::BindGroupLayout,
+    equirect: wgpu::RenderPipeline,
+    resample: wgpu::RenderPipeline,
+    irradiance: wgpu::RenderPipeline,
+    specular: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+}
+
+impl Prefilter {
+    fn new(device: &wgpu::Device) -> Self {
+        let params_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ibl face params"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let cube_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ibl cube source"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let equirect_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ibl equirect source"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        });
+
+        let pipeline = |label: &str,
+                        source: &str,
+                        source_layout: &wgpu::BindGroupLayout,
+                        entry_point: &str| {
+            let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(label),
+                source: wgpu::ShaderSource::Wgsl(
+                    format!("{}\n{}", include_str!("cube_face.wgsl"), source).into(),
+                ),
+            });
+
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &[&params_layout, source_layout],
+                push_constant_ranges: &[],
+            });
+
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader_module,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader_module,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: Environment::FORMAT,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+
+        let prefilter = include_str!("prefilter.wgsl");
+        Self {
+            equirect: pipeline(
+                "ibl equirect",
+                include_str!("equirect.wgsl"),
+                &equirect_layout,
+                "fs_main",
+            ),
+            resample: pipeline("ibl resample", prefilter, &cube_layout, "fs_resample"),
+            irradiance: pipeline("ibl irradiance", prefilter, &cube_layout, "fs_irradiance"),
+            specular: pipeline("ibl specular", prefilter, &cube_layout, "fs_specular"),
+            sampler: create_sampler(device),
+            params_layout,
+            cube_layout,
+            equirect_layout,
+        }
+    }
+
+    /// Binds the mip levels `mip_levels` of `texture` as a cube map source.
+    fn cube_source(
+        &self,
+        device: &wgpu::Device,
+        texture: &wgpu::Texture,
+        mip_levels: std::ops::Range<u32>,
+    ) -> wgpu::BindGroup {
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            base_mip_level: mip_levels.start,
+            mip_level_count: Some(mip_levels.len() as u32),
+            ..Default::default()
+        });
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ibl cube source"),
+            layout: &self.cube_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        })
+    }
+
+    /// Renders all six faces of a mip level of `target`.
+    #[allow(clippy::too_many_arguments)]
+    fn render_faces(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        source: &wgpu::BindGroup,
+        source_size: u32,
+        target: &wgpu::Texture,
+        mip_level: u32,
+        roughness: f32,
+    ) {
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as usize;
+        let stride = std::mem::size_of::<FaceParams>().next_multiple_of(alignment);
+        let mut contents = vec![0u8; stride * 6];
+        for face in 0..6 {
+            let params = FaceParams {
+                face: face as u32,
+                roughness,
+                source_size: source_size as f32,
+                _padding: 0,
+            };
+            contents[face * stride..][..std::mem::size_of::<FaceParams>()]
+                .copy_from_slice(bytemuck::bytes_of(&params));
+        }
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("ibl face params"),
+            usage: wgpu::BufferUsages::UNIFORM,
+            contents: &contents,
+        });
+        let params = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ibl face params"),
+            layout: &self.params_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<FaceParams>() as u64),
+                }),
+            }],
+        });
+
+        for face in 0..6 {
+            let view = target.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_mip_level: mip_level,
+                mip_level_count: Some(1),
+                base_array_layer: face,
+                array_layer_count: Some(1),
+                ..Default::default()
+            });
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("ibl face"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, &params, &[face * stride as u32]);
+            render_pass.set_bind_group(1, source, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+fn create_sampler(device: &wgpu::Device) -> wgpu::Sampler {
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("environment"),
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    })
+}
+
+fn create_cube_texture(
+    device: &wgpu::Device,
+    label: &str,
+    size: u32,
+    mip_level_count: u32,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 6,
+        },
+        dimension: wgpu::TextureDimension::D2,
+        mip_level_count,
+        sample_count: 1,
+        format: Environment::FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
+
+/// Environment cube map with the maps prefiltered from it for image-based
+/// lighting: diffuse irradiance and GGX specular reflections, with the
+/// roughness increasing over the mip levels.
+///
+/// Bound as one group with the environment, irradiance and specular cube
+/// maps at bindings 0 to 2 and a trilinear sampler at binding 3.
+pub struct Environment {
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Environment {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    const IRRADIANCE_SIZE: u32 = 32;
+    const SPECULAR_SIZE: u32 = 128;
+    const SPECULAR_MIP_LEVELS: u32 = 5;
+
+    /// Uses six low dynamic range faces as the environment.
+    pub fn from_faces(device: &wgpu::Device, queue: &wgpu::Queue, faces: &CubeFaces) -> Self {
+        let size = faces[0].width();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("environment"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            dimension: wgpu::TextureDimension::D2,
+            mip_level_count: 1,
+            sample_count: 1,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        for (layer, face) in faces.iter().enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                face,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * size),
+                    rows_per_image: Some(size),
+                },
+                wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let prefilter = Prefilter::new(device);
+        let source = prefilter.cube_source(device, &texture, 0..1);
+        Self::build(device, queue, &prefilter, size, |encoder, target| {
+            prefilter.render_faces(
+                device,
+                encoder,
+                &prefilter.resample,
+                &source,
+                size,
+                target,
+                0,
+                0.0,
+            );
+        })
+    }
+
+    /// Projects a high dynamic range equirectangular panorama onto the faces
+    /// of the environment on the GPU.
+    pub fn from_equirect(device: &wgpu::Device, queue: &wgpu::Queue, image: &Rgba32FImage) -> Self {
+        let max_size = device.limits().max_texture_dimension_2d;
+        let resized;
+        let image = if image.width() > max_size || image.height() > max_size {
+            log::warn!("scaling the panorama down to {} texels", max_size);
+            let scale = max_size as f32 / image.width().max(image.height()) as f32;
+            resized = image::imageops::resize(
+                image,
+                (image.width() as f32 * scale) as u32,
+                (image.height() as f32 * scale) as u32,
+                image::imageops::FilterType::Triangle,
+            );
+            &resized
+        } else {
+            image
+        };
+
+        let panorama = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("environment panorama"),
+                size: wgpu::Extent3d {
+                    width: image.width(),
+                    height: image.height(),
+                    depth_or_array_layers: 1,
+                },
+                dimension: wgpu::TextureDimension::D2,
+                mip_level_count: 1,
+                sample_count: 1,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            bytemuck::cast_slice(image.as_raw()),
+        );
+
+        let prefilter = Prefilter::new(device);
+        let source = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ibl equirect source"),
+            layout: &prefilter.equirect_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(
+                    &panorama.create_view(&wgpu::TextureViewDescriptor::default()),
+                ),
+            }],
+        });
+        // A quarter of the panorama width keeps roughly the texel density
+        let size = (image.width() / 4).next_power_of_two().clamp(64, 1024);
+        Self::build(device, queue, &prefilter, size, |encoder, target| {
+            prefilter.render_faces(
+                device,
+                encoder,
+                &prefilter.equirect,
+                &source,
+                image.width(),
+                target,
+                0,
+                0.0,
+            );
+        })
+    }
+
+    /// Renders the first mip level of the environment with `render_base`,
+    /// then the remaining mip levels and the prefiltered maps.
+    fn build(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        prefilter: &Prefilter,
+        size: u32,
+        render_base: impl FnOnce(&mut wgpu::CommandEncoder, &wgpu::Texture),
+    ) -> Self {
+        let mip_level_count = size.ilog2() + 1;
+        let environment = create_cube_texture(device, "environment", size, mip_level_count);
+        let irradiance = create_cube_texture(device, "irradiance", Self::IRRADIANCE_SIZE, 1);
+        let specular = create_cube_texture(
+            device,
+            "specular",
+            Self::SPECULAR_SIZE,
+            Self::SPECULAR_MIP_LEVELS,
+        );
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("ibl prefilter"),
+        });
+        render_base(&mut encoder, &environment);
+
+        for mip_level in 1..mip_level_count {
+            let source = prefilter.cube_source(device, &environment, mip_level - 1..mip_level);
+            prefilter.render_faces(
+                device,
+                &mut encoder,
+                &prefilter.resample,
+                &source,
+                size,
+                &environment,
+                mip_level,
+                0.0,
+            );
+        }
+
+        let source = prefilter.cube_source(device, &environment, 0..mip_level_count);
+        prefilter.render_faces(
+            device,
+            &mut encoder,
+            &prefilter.irradiance,
+            &source,
+            size,
+            &irradiance,
+            0,
+            0.0,
+        );
+        for mip_level in 0..Self::SPECULAR_MIP_LEVELS {
+            prefilter.render_faces(
+                device,
+                &mut encoder,
+                &prefilter.specular,
+                &source,
+                size,
+                &specular,
+                mip_level,
+                mip_level as f32 / (Self::SPECULAR_MIP_LEVELS - 1) as f32,
+            );
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let cube_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::Cube,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("environment"),
+            entries: &[
+                cube_entry(0),
+                cube_entry(1),
+                cube_entry(2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let cube_view = |texture: &wgpu::Texture| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::Cube),
+                ..Default::default()
+            })
+        };
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("environment"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&cube_view(&environment)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&cube_view(&irradiance)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&cube_view(&specular)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&create_sampler(device)),
+                },
+            ],
+        });
+
+        Self { layout, bind_group }
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+}
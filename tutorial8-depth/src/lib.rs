@@ -1,44 +1,433 @@
-use instant::Instant;
-
-use glam::{vec3, Mat4, Quat, Vec3};
+mod adapter;
+mod benchmark;
+mod error;
+mod graph;
+mod hud;
+mod ibl;
+mod oit;
+mod options;
+mod overdraw;
+mod pacing;
+mod picking;
+mod postprocess;
+mod queries;
+mod recording;
+mod resources;
+#[cfg(not(target_arch = "wasm32"))]
+mod screenshot;
+mod settings;
+mod skybox;
+mod sort;
+mod text;
+mod ui;
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+
+use clock::Clock;
+use glam::{vec3, Mat4, Quat, Vec2, Vec3};
+use image::imageops::FilterType;
 use wgpu::util::DeviceExt;
 use winit::{
     event::*,
-    event_loop::{self, EventLoop},
+    event_loop::{self, ControlFlow, EventLoop},
+    keyboard::Key,
     window::{Window, WindowBuilder},
 };
 
+use benchmark::PrepassBenchmark;
+pub use error::Error;
+use graph::{RenderGraph, Resource, TextureDesc, TransientPool};
+use hud::{Hud, HudInfo};
+use ibl::Environment;
+use oit::WeightedBlendedOit;
+pub use options::Options;
+use overdraw::Overdraw;
+use pacing::FrameLimiter;
+use picking::{IdPicking, InstanceGrid, Ray};
+use postprocess::PostProcess;
+use queries::{OcclusionCulling, PipelineStatistics, QueryReport};
+use resources::{Handle, Resources};
+use settings::{
+    DepthPrepass, InstanceStyle, Picking, SamplerFilters, Settings, Transparency, Wave,
+};
+use skybox::Skybox;
+use sort::GpuSort;
+use text::TextRenderer;
+use ui::DebugUi;
+
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
+/// The window with its surface and UI, absent when recording headless.
+struct Presentation<'w> {
+    window: &'w Window,
+    surface: wgpu::Surface<'w>,
+    ui: DebugUi,
+}
+
 struct State<'w> {
     device: wgpu::Device,
     queue: wgpu::Queue,
-    surface: wgpu::Surface<'w>,
+    presentation: Option<Presentation<'w>>,
+    /// Also describes the output without a window
     surface_config: wgpu::SurfaceConfiguration,
+    present_modes: Vec<wgpu::PresentMode>,
+    frame_limiter: FrameLimiter,
 
-    render_pipeline: wgpu::RenderPipeline,
+    resources: Resources,
 
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
-    num_indices: u32,
+    scene_shader: Handle<wgpu::ShaderModule>,
+    scene_layout: Handle<wgpu::PipelineLayout>,
+    scene_pipelines: ScenePipelines,
+    /// Wireframes are drawn with barycentric coordinates otherwise
+    polygon_mode_line: bool,
 
-    uniform_buffer: wgpu::Buffer,
-    uniform_bind_group: wgpu::BindGroup,
-    texture_bind_group: wgpu::BindGroup,
+    vertex_buffer: Handle<wgpu::Buffer>,
+    index_buffer: Handle<wgpu::Buffer>,
+    num_indices: u32,
+    /// Vertex and index buffers, only while barycentric wireframes are drawn
+    wireframe_buffers: Option<(Handle<wgpu::Buffer>, Handle<wgpu::Buffer>)>,
+
+    uniform_buffer: Handle<wgpu::Buffer>,
+    uniform_bind_group: Handle<wgpu::BindGroup>,
+    texture: Handle<wgpu::TextureView>,
+    texture_layers: u32,
+    /// Images besides the happy tree, reloaded with the "r" key
+    texture_paths: Vec<PathBuf>,
+    texture_bind_group_layout: Handle<wgpu::BindGroupLayout>,
+    texture_bind_group: Handle<wgpu::BindGroup>,
 
     instances: Vec<Instance>,
-    instance_buffer: wgpu::Buffer,
-
-    depth_texture_view: wgpu::TextureView,
+    /// The instances of the current frame, before sorting
+    raws: Vec<InstanceRaw>,
+    instance_buffer: Handle<wgpu::Buffer>,
+    /// Instance index per position in the instance buffer while sorting on
+    /// the CPU, empty otherwise
+    sort_order: Vec<u32>,
+    /// Sorts the instances on the GPU where compute shaders are available
+    gpu_sort: Option<GpuSort>,
+
+    /// Samples per pixel of the scene, resolved into the post-processing input
+    sample_count: u32,
+    /// Depth and multisampled targets, allocated by the render graph
+    transients: RefCell<TransientPool>,
+    post_process: PostProcess,
+    oit: WeightedBlendedOit,
+    overdraw: Overdraw,
+    prepass_benchmark: Option<PrepassBenchmark>,
+    environment: Environment,
+    skybox: Skybox,
+
+    occlusion: OcclusionCulling,
+    pipeline_statistics: Option<PipelineStatistics>,
+    query_report: QueryReport,
+
+    text: TextRenderer,
+    hud: Hud,
+
+    settings: Settings,
+    screenshot_requested: bool,
+    /// Logs the render graph of the next frame
+    graph_dump_requested: bool,
+
+    view_proj: Mat4,
+    /// Last cursor position in physical pixels
+    cursor: Vec2,
+    instance_grid: InstanceGrid,
+    id_picking: IdPicking,
+    /// Index of the highlighted instance
+    picked: Option<usize>,
 
     size: winit::dpi::PhysicalSize<u32>,
-    window: &'w Window,
-    instant: Instant,
-    time: f32,
+    clock: Clock,
     value_d: f32,
 }
 
+/// Pipeline variants drawing the instances, one per [`Transparency`] mode.
+///
+/// They share the rasterizer state and are recreated together when it or the
+/// depth pre-pass changes.
+struct ScenePipelines {
+    opaque: Handle<wgpu::RenderPipeline>,
+    cutout: Handle<wgpu::RenderPipeline>,
+    /// Only with MSAA
+    alpha_to_coverage: Option<Handle<wgpu::RenderPipeline>>,
+    /// Blends the instances over the opaque scene without writing depth
+    sorted: Handle<wgpu::RenderPipeline>,
+    /// Accumulates the instances for order-independent transparency
+    weighted_blended: Handle<wgpu::RenderPipeline>,
+    /// Count the fragments of the instances, with depth writes and the alpha
+    /// cutoff, with depth writes for alpha to coverage (only with MSAA) and
+    /// without depth writes
+    overdraw: Handle<wgpu::RenderPipeline>,
+    overdraw_alpha_to_coverage: Option<Handle<wgpu::RenderPipeline>>,
+    overdraw_blended: Handle<wgpu::RenderPipeline>,
+    /// Only with a depth pre-pass
+    prepass: Option<PrepassPipelines>,
+}
+
+/// Pipelines of the depth pre-pass, created together with it.
+struct PrepassPipelines {
+    /// Depth-only pipelines for `opaque` and `cutout`
+    depth_only: Handle<wgpu::RenderPipeline>,
+    depth_only_cutout: Handle<wgpu::RenderPipeline>,
+    /// Counts the fragments against the depth of the pre-pass
+    overdraw: Handle<wgpu::RenderPipeline>,
+}
+
+impl ScenePipelines {
+    /// `barycentric_wireframe` if the fragment shaders discard all but the
+    /// triangle edges, which the depth pre-pass has to do as well.
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        device: &wgpu::Device,
+        resources: &mut Resources,
+        layout: Handle<wgpu::PipelineLayout>,
+        shader_module: Handle<wgpu::ShaderModule>,
+        sample_count: u32,
+        primitive: wgpu::PrimitiveState,
+        depth_prepass: DepthPrepass,
+        barycentric_wireframe: bool,
+    ) -> Self {
+        let mut pipeline = |label: &str,
+                            entry_point: Option<&str>,
+                            targets: &[Option<wgpu::ColorTargetState>],
+                            depth_write_enabled,
+                            depth_compare,
+                            alpha_to_coverage_enabled| {
+            resources.create(label, |resources, label| {
+                let shader_module = &resources[shader_module];
+                device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label,
+                    layout: Some(&resources[layout]),
+                    vertex: wgpu::VertexState {
+                        module: shader_module,
+                        entry_point: "vs_main",
+                        buffers: &[MyVertex::layout(), InstanceRaw::layout()],
+                    },
+                    fragment: entry_point.map(|entry_point| wgpu::FragmentState {
+                        module: shader_module,
+                        entry_point,
+                        targets,
+                    }),
+                    primitive,
+                    depth_stencil: Some(wgpu::DepthStencilState {
+                        format: MyTexture::DEPTH_FORMAT,
+                        depth_write_enabled,
+                        depth_compare,
+                        stencil: wgpu::StencilState::default(),
+                        bias: wgpu::DepthBiasState::default(),
+                    }),
+                    multisample: wgpu::MultisampleState {
+                        count: sample_count,
+                        mask: !0,
+                        alpha_to_coverage_enabled,
+                    },
+                    multiview: None,
+                })
+            })
+        };
+
+        let target = |blend| {
+            [Some(wgpu::ColorTargetState {
+                format: PostProcess::HDR_FORMAT,
+                blend: Some(blend),
+                write_mask: wgpu::ColorWrites::ALL,
+            })]
+        };
+        let opaque = target(wgpu::BlendState::REPLACE);
+        let less = wgpu::CompareFunction::Less;
+        // After a pre-pass the depth is final, the color pass only tests it
+        let (depth_write_enabled, depth_compare) = match depth_prepass.compare() {
+            Some(compare) => (false, compare),
+            None => (true, less),
+        };
+        Self {
+            opaque: pipeline(
+                "scene opaque",
+                Some("fs_main"),
+                &opaque,
+                depth_write_enabled,
+                depth_compare,
+                false,
+            ),
+            cutout: pipeline(
+                "scene cutout",
+                Some("fs_cutout"),
+                &opaque,
+                depth_write_enabled,
+                depth_compare,
+                false,
+            ),
+            alpha_to_coverage: (sample_count > 1).then(|| {
+                pipeline(
+                    "scene alpha to coverage",
+                    Some("fs_main"),
+                    &opaque,
+                    true,
+                    less,
+                    true,
+                )
+            }),
+            sorted: pipeline(
+                "scene sorted",
+                Some("fs_main"),
+                &target(wgpu::BlendState::ALPHA_BLENDING),
+                false,
+                less,
+                false,
+            ),
+            weighted_blended: pipeline(
+                "scene weighted blended",
+                Some("fs_oit"),
+                &WeightedBlendedOit::accumulation_targets(),
+                false,
+                less,
+                false,
+            ),
+            overdraw: pipeline(
+                "scene overdraw",
+                Some("fs_overdraw_cutout"),
+                &Overdraw::counter_target(),
+                true,
+                less,
+                false,
+            ),
+            overdraw_alpha_to_coverage: (sample_count > 1).then(|| {
+                pipeline(
+                    "scene overdraw alpha to coverage",
+                    Some("fs_overdraw"),
+                    &Overdraw::counter_target(),
+                    true,
+                    less,
+                    false,
+                )
+            }),
+            overdraw_blended: pipeline(
+                "scene overdraw blended",
+                Some("fs_overdraw"),
+                &Overdraw::counter_target(),
+                false,
+                less,
+                false,
+            ),
+            prepass: depth_prepass.compare().map(|compare| PrepassPipelines {
+                // Opaque instances have a cutoff of zero, only the edges
+                // discard
+                depth_only: pipeline(
+                    "scene depth only",
+                    barycentric_wireframe.then_some("fs_depth_cutout"),
+                    &[],
+                    true,
+                    less,
+                    false,
+                ),
+                depth_only_cutout: pipeline(
+                    "scene depth only cutout",
+                    Some("fs_depth_cutout"),
+                    &[],
+                    true,
+                    less,
+                    false,
+                ),
+                overdraw: pipeline(
+                    "scene overdraw after pre-pass",
+                    Some("fs_overdraw_cutout"),
+                    &Overdraw::counter_target(),
+                    false,
+                    compare,
+                    false,
+                ),
+            }),
+        }
+    }
+
+    /// Drops the pipelines, before they are recreated.
+    fn release(self, resources: &mut Resources) {
+        let pipelines = [
+            self.opaque,
+            self.cutout,
+            self.sorted,
+            self.weighted_blended,
+            self.overdraw,
+            self.overdraw_blended,
+        ];
+        let prepass = self.prepass.into_iter().flat_map(|prepass| {
+            [
+                prepass.depth_only,
+                prepass.depth_only_cutout,
+                prepass.overdraw,
+            ]
+        });
+        for pipeline in pipelines
+            .into_iter()
+            .chain(self.alpha_to_coverage)
+            .chain(self.overdraw_alpha_to_coverage)
+            .chain(prepass)
+        {
+            resources.release(pipeline);
+        }
+    }
+
+    fn get(&self, transparency: Transparency) -> Handle<wgpu::RenderPipeline> {
+        match transparency {
+            Transparency::Opaque => self.opaque,
+            Transparency::Cutout => self.cutout,
+            Transparency::AlphaToCoverage => self.alpha_to_coverage.unwrap_or(self.cutout),
+            Transparency::Sorted => self.sorted,
+            Transparency::WeightedBlended => self.weighted_blended,
+        }
+    }
+
+    /// The depth-only pipeline to draw before `transparency` and the overdraw
+    /// pipeline testing against it, if it has a depth pre-pass.
+    fn prepass(
+        &self,
+        transparency: Transparency,
+    ) -> Option<(Handle<wgpu::RenderPipeline>, Handle<wgpu::RenderPipeline>)> {
+        let prepass = self.prepass.as_ref()?;
+        let depth_only = match transparency {
+            Transparency::Opaque => prepass.depth_only,
+            Transparency::Cutout => prepass.depth_only_cutout,
+            // Without a multisampled pre-pass of the coverage
+            Transparency::AlphaToCoverage if self.alpha_to_coverage.is_some() => return None,
+            Transparency::AlphaToCoverage => prepass.depth_only_cutout,
+            Transparency::Sorted | Transparency::WeightedBlended => return None,
+        };
+        Some((depth_only, prepass.overdraw))
+    }
+
+    fn depth_prepass(&self, transparency: Transparency) -> Option<Handle<wgpu::RenderPipeline>> {
+        self.prepass(transparency).map(|(depth_only, _)| depth_only)
+    }
+
+    /// Counts the fragments the same way `transparency` tests depth and
+    /// discards them.
+    fn overdraw(&self, transparency: Transparency) -> Handle<wgpu::RenderPipeline> {
+        match self.prepass(transparency) {
+            Some((_, overdraw)) => overdraw,
+            None if transparency.is_blended() => self.overdraw_blended,
+            None if transparency == Transparency::AlphaToCoverage => {
+                self.overdraw_alpha_to_coverage.unwrap_or(self.overdraw)
+            }
+            None => self.overdraw,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct WorldUniform {
+    view_proj: [f32; 16],
+    eye: [f32; 3],
+    reflectivity: f32,
+    roughness: f32,
+    ibl: u32,
+    alpha_cutoff: f32,
+    wireframe: u32,
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 struct MyVertex {
@@ -60,6 +449,32 @@ impl MyVertex {
     }
 }
 
+/// A pentagon, drawn on every instance
+const VERTICES: &[MyVertex] = &[
+    MyVertex {
+        position: [-0.0868241, 0.49240386, 0.0],
+        tex_coord: [0.4131759, 0.00759614],
+    }, // A
+    MyVertex {
+        position: [-0.49513406, 0.06958647, 0.0],
+        tex_coord: [0.0048659444, 0.43041354],
+    }, // B
+    MyVertex {
+        position: [-0.21918549, -0.44939706, 0.0],
+        tex_coord: [0.28081453, 0.949397],
+    }, // C
+    MyVertex {
+        position: [0.35966998, -0.3473291, 0.0],
+        tex_coord: [0.85967, 0.84732914],
+    }, // D
+    MyVertex {
+        position: [0.44147372, 0.2347359, 0.0],
+        tex_coord: [0.9414737, 0.2652641],
+    }, // E
+];
+
+const INDICES: &[u16] = &[0, 1, 4, 1, 2, 4, 2, 3, 4];
+
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct Instance {
@@ -68,34 +483,113 @@ struct Instance {
 }
 
 impl Instance {
-    fn to_raw(self, t: f32) -> InstanceRaw {
+    /// Builds the grid spanning `-n..=n` on both axes.
+    ///
+    /// Instances are grouped into square chunks for occlusion culling; the
+    /// ranges of the chunks are returned alongside.
+    fn grid(n: i32) -> (Vec<Instance>, Vec<std::ops::Range<u32>>) {
+        let mut instances = Vec::new();
+        let mut chunks = Vec::new();
+        const CHUNK: i32 = 32;
+        for cz in (-n..=n).step_by(CHUNK as usize) {
+            for cx in (-n..=n).step_by(CHUNK as usize) {
+                let start = instances.len() as u32;
+                for z in cz..(cz + CHUNK).min(n + 1) {
+                    for x in cx..(cx + CHUNK).min(n + 1) {
+                        instances.push(Instance { x, z });
+                    }
+                }
+                chunks.push(start..instances.len() as u32);
+            }
+        }
+        (instances, chunks)
+    }
+
+    /// Layer of the texture array the instance is drawn with.
+    fn layer(self, layers: u32) -> u32 {
+        (self.x * 7 + self.z * 13).rem_euclid(layers as i32) as u32
+    }
+
+    fn to_raw(
+        self,
+        t: f32,
+        wave: &Wave,
+        style: &InstanceStyle,
+        layers: u32,
+        highlighted: bool,
+    ) -> InstanceRaw {
         let d = ((self.x * self.x + self.z * self.z) as f32).sqrt();
+        let ripple = (d + t * wave.ripple_speed).sin() * d * wave.ripple_amplitude;
         let translation = Vec3::new(
             self.x as f32,
-            (d + t * 10.).sin() * d / 10. + (d / 100.).powf(3.) * (t * 1.0).sin() * 10.0,
+            ripple
+                + (d / wave.swell_radius).powf(wave.swell_exponent)
+                    * (t * wave.swell_speed).sin()
+                    * wave.swell_amplitude,
             self.z as f32,
         );
         let rotation = Quat::from_axis_angle(
-            Vec3::new(self.x as f32, (d + t * 10.).sin() * d / 100., self.z as f32).normalize(),
+            Vec3::new(self.x as f32, ripple / 10., self.z as f32).normalize(),
             t,
         );
 
+        let mut flags = 0;
+        if style.color_by_height {
+            flags |= InstanceRaw::COLOR_MAP;
+        }
+        if highlighted {
+            flags |= InstanceRaw::HIGHLIGHT;
+        }
+
         // raw item for the instance buffer
         InstanceRaw {
             matrix: Mat4::from_rotation_translation(rotation, translation).to_cols_array(),
+            tint: [style.tint[0], style.tint[1], style.tint[2], 1.0],
+            layer: self.layer(layers),
+            scale: style.scale,
+            value: translation.y / style.height_range * 0.5 + 0.5,
+            flags,
         }
     }
 }
 
+/// Per-instance attributes; new ones get the next shader locations.
+///
+/// Mirrored in sort.wgsl, where it is read as storage; the size is kept a
+/// multiple of 16 bytes to match.
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct InstanceRaw {
     matrix: [f32; 16],
+    /// Multiplies the texture color
+    tint: [f32; 4],
+    /// Layer of the texture array
+    layer: u32,
+    /// Uniform scale of the quad
+    scale: f32,
+    /// Mapped to a color ramp from 0 to 1 with [`InstanceRaw::COLOR_MAP`]
+    value: f32,
+    flags: u32,
 }
 
 impl InstanceRaw {
+    /// Tints the instance by its value
+    const COLOR_MAP: u32 = 1;
+    /// Marks the picked instance
+    const HIGHLIGHT: u32 = 2;
+
     fn layout() -> wgpu::VertexBufferLayout<'static> {
-        const ATRIBUTES: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![2 => Float32x4, 3 => Float32x4, 4 => Float32x4, 5 => Float32x4];
+        const ATRIBUTES: [wgpu::VertexAttribute; 9] = wgpu::vertex_attr_array![
+            2 => Float32x4,
+            3 => Float32x4,
+            4 => Float32x4,
+            5 => Float32x4,
+            6 => Float32x4,
+            7 => Uint32,
+            8 => Float32,
+            9 => Float32,
+            10 => Uint32,
+        ];
 
         wgpu::VertexBufferLayout {
             array_stride: std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
@@ -107,24 +601,81 @@ impl InstanceRaw {
 
 struct MyTexture {
     view: wgpu::TextureView,
+    layers: u32,
 }
 
 impl MyTexture {
-    pub fn from_image(
+    const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+    fn sampler_descriptor(filters: &SamplerFilters) -> wgpu::SamplerDescriptor<'static> {
+        wgpu::SamplerDescriptor {
+            label: Some("texture array"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: filters.mag_filter,
+            min_filter: filters.min_filter,
+            mipmap_filter: filters.mipmap_filter,
+            ..Default::default()
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        resources: &Resources,
+        label: Option<&str>,
+        layout: Handle<wgpu::BindGroupLayout>,
+        texture: Handle<wgpu::TextureView>,
+        sampler: Handle<wgpu::Sampler>,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label,
+            layout: &resources[layout],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&resources[texture]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&resources[sampler]),
+                },
+            ],
+        })
+    }
+
+    /// Creates a texture array with a layer per image, resized to the size of
+    /// the first one.
+    pub fn from_images(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        image: image::DynamicImage,
+        label: Option<&str>,
+        images: &[image::RgbaImage],
     ) -> Self {
-        let color_image = image.to_rgba8();
-        let dim = color_image.dimensions();
+        let dim = images[0].dimensions();
+        let mut layers: Vec<_> = images
+            .iter()
+            .map(|image| {
+                if image.dimensions() == dim {
+                    image.clone()
+                } else {
+                    image::imageops::resize(image, dim.0, dim.1, FilterType::Triangle)
+                }
+            })
+            .collect();
+        // GL picks the texture target from the layer count, a single layer
+        // could not be viewed as an array
+        if layers.len() == 1 {
+            layers.push(layers[0].clone());
+        }
         let size = wgpu::Extent3d {
             width: dim.0,
             height: dim.1,
-            depth_or_array_layers: 1,
+            depth_or_array_layers: layers.len() as u32,
         };
 
         let color_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: None,
+            label,
             size,
             dimension: wgpu::TextureDimension::D2,
             mip_level_count: 1,
@@ -135,103 +686,148 @@ impl MyTexture {
         });
 
         // Write image data to the texture
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &color_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &color_image,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dim.0),
-                rows_per_image: Some(dim.1),
-            },
-            size,
-        );
+        for (layer, image) in layers.iter().enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &color_texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                image,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * dim.0),
+                    rows_per_image: Some(dim.1),
+                },
+                wgpu::Extent3d {
+                    depth_or_array_layers: 1,
+                    ..size
+                },
+            );
+        }
 
         Self {
-            view: color_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            view: color_texture.create_view(&wgpu::TextureViewDescriptor {
+                label,
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                ..Default::default()
+            }),
+            layers: images.len() as u32,
         }
     }
 
     pub fn create_depth_texture(
         device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
+        width: u32,
+        height: u32,
+        sample_count: u32,
     ) -> Self {
         let size = wgpu::Extent3d {
-            width: config.width,
-            height: config.height,
+            width,
+            height,
             depth_or_array_layers: 1,
         };
 
         let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: None,
+            label: Some("depth texture"),
             size,
             dimension: wgpu::TextureDimension::D2,
             mip_level_count: 1,
-            sample_count: 1,
-            format: wgpu::TextureFormat::Depth32Float,
+            sample_count,
+            format: Self::DEPTH_FORMAT,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
             view_formats: &[],
         });
 
         Self {
             view: depth_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            layers: 1,
         }
     }
 }
 
 impl<'w> State<'w> {
-    async fn new(window: &'w Window) -> Self {
-        let size = window.inner_size();
+    /// Renders offscreen without `window`, at the size the recording
+    /// resizes the targets to.
+    async fn new(window: Option<&'w Window>, options: &Options) -> Result<Self, Error> {
+        let size = window.map_or(winit::dpi::PhysicalSize::new(1, 1), |window| {
+            window.inner_size()
+        });
 
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
+            backends: adapter::backends(options),
             ..Default::default()
         });
-        let surface = instance.create_surface(window).unwrap();
-
-        let (device, queue, surface_config) = {
-            let adapter = instance
-                .request_adapter(&wgpu::RequestAdapterOptions {
-                    power_preference: wgpu::PowerPreference::default(),
-                    force_fallback_adapter: false,
-                    compatible_surface: Some(&surface),
-                })
+        let surface = window
+            .map(|window| instance.create_surface(window))
+            .transpose()?;
+
+        let (
+            device,
+            queue,
+            surface_config,
+            present_modes,
+            compute_shaders,
+            polygon_mode_line,
+            sample_count,
+        ) = {
+            let adapter = adapter::select(&instance, surface.as_ref(), options)
                 .await
-                .unwrap();
+                .ok_or(Error::NoAdapter)?;
+            let info = adapter.get_info();
+            log::info!("adapter: {} ({:?})", info.name, info.backend);
 
             let (device, queue) = adapter
                 .request_device(
                     &wgpu::DeviceDescriptor {
-                        label: None,
-                        required_features: wgpu::Features::empty(),
+                        label: Some("device"),
+                        // Pipeline statistics are only reported and line
+                        // rasterization only used where available
+                        required_features: adapter.features()
+                            & (wgpu::Features::PIPELINE_STATISTICS_QUERY
+                                | wgpu::Features::POLYGON_MODE_LINE),
                         required_limits: wgpu::Limits::downlevel_defaults()
                             .using_resolution(adapter.limits()),
                     },
                     None,
                 )
-                .await
-                .unwrap();
+                .await?;
 
             // let config = surface
             //     .get_default_config(&adapter, size.width.max(1), size.height.max(1))
             //     .unwrap();
-            let surface_caps = surface.get_capabilities(&adapter);
+            let surface_caps = match &surface {
+                Some(surface) => surface.get_capabilities(&adapter),
+                // Describes the recording target
+                None => wgpu::SurfaceCapabilities {
+                    formats: vec![wgpu::TextureFormat::Rgba8UnormSrgb],
+                    present_modes: vec![wgpu::PresentMode::Fifo],
+                    alpha_modes: vec![wgpu::CompositeAlphaMode::Auto],
+                    usages: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                },
+            };
             let format = surface_caps
                 .formats
                 .iter()
                 .find(|&f| f.is_srgb())
                 .unwrap_or(&surface_caps.formats[0]);
             let surface_config = wgpu::SurfaceConfiguration {
-                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                // Screenshots are copied directly from the surface texture
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | (surface_caps.usages & wgpu::TextureUsages::COPY_SRC),
                 format: *format,
                 width: size.width.max(1),
                 height: size.height.max(1),
-                present_mode: surface_caps.present_modes[0],
-                desired_maximum_frame_latency: 2,
+                present_mode: pacing::select_present_mode(
+                    options.present_mode,
+                    &surface_caps.present_modes,
+                ),
+                desired_maximum_frame_latency: options.frame_latency.unwrap_or(2),
                 alpha_mode: surface_caps.alpha_modes[0],
                 view_formats: if !format.is_srgb() {
                     vec![format.add_srgb_suffix()]
@@ -240,224 +836,584 @@ impl<'w> State<'w> {
                 },
             };
 
-            (device, queue, surface_config)
+            (
+                device,
+                queue,
+                surface_config,
+                surface_caps.present_modes,
+                GpuSort::is_supported(&adapter),
+                adapter
+                    .features()
+                    .contains(wgpu::Features::POLYGON_MODE_LINE),
+                Self::supported_sample_count(&adapter, options.msaa.unwrap_or(1)),
+            )
         };
 
-        surface.configure(&device, &surface_config);
+        if let Some(surface) = &surface {
+            surface.configure(&device, &surface_config);
+        }
+
+        let mut resources = Resources::new();
 
-        let uniform_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: None,
-                entries: &[wgpu::BindGroupLayoutEntry {
+        let uniform_bind_group_layout = resources.bind_group_layout(
+            &device,
+            "uniforms",
+            &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::all(),
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        );
+
+        let texture_bind_group_layout = resources.bind_group_layout(
+            &device,
+            "texture array",
+            &[
+                wgpu::BindGroupLayoutEntry {
                     binding: 0,
                     visibility: wgpu::ShaderStages::all(),
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
                     },
                     count: None,
-                }],
-            });
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::all(),
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        );
 
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: None,
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::all(),
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::all(),
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-            });
+        let environment = Self::load_environment(&device, &queue, options);
+        let skybox = Skybox::new(
+            &device,
+            PostProcess::HDR_FORMAT,
+            MyTexture::DEPTH_FORMAT,
+            sample_count,
+            &environment,
+        );
 
-        // Construct a render pipeline
-        let render_pipeline = {
-            let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-                label: None,
-                source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-            });
+        let settings = Settings {
+            // Show the sky given on the command line
+            skybox: options.skybox.is_some() || options.environment.is_some(),
+            ..Settings::default()
+        };
 
-            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: None,
-                bind_group_layouts: &[&uniform_bind_group_layout, &texture_bind_group_layout],
+        // Construct the render pipelines
+        let scene_shader = resources.create("scene shader", |_, label| {
+            device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label,
+                source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
+            })
+        });
+        let scene_layout = resources.create("scene", |resources, label| {
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label,
+                bind_group_layouts: &[
+                    &resources[uniform_bind_group_layout],
+                    &resources[texture_bind_group_layout],
+                    environment.layout(),
+                ],
                 push_constant_ranges: &[],
-            });
-
-            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: None,
-                layout: Some(&layout),
-                vertex: wgpu::VertexState {
-                    module: &shader_module,
-                    entry_point: "vs_main",
-                    buffers: &[MyVertex::layout(), InstanceRaw::layout()],
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &shader_module,
-                    entry_point: "fs_main",
-                    targets: &[Some(wgpu::ColorTargetState {
-                        format: surface_config.format.add_srgb_suffix(),
-                        blend: Some(wgpu::BlendState::REPLACE),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
-                }),
-                primitive: wgpu::PrimitiveState {
-                    topology: wgpu::PrimitiveTopology::TriangleList,
-                    strip_index_format: None,
-                    front_face: wgpu::FrontFace::Ccw,
-                    // cull_mode: Some(wgpu::Face::Back),
-                    cull_mode: None,
-                    unclipped_depth: false,
-                    polygon_mode: wgpu::PolygonMode::Fill,
-                    conservative: false,
-                },
-                depth_stencil: Some(wgpu::DepthStencilState {
-                    format: wgpu::TextureFormat::Depth32Float,
-                    depth_write_enabled: true,
-                    depth_compare: wgpu::CompareFunction::Less,
-                    stencil: wgpu::StencilState::default(),
-                    bias: wgpu::DepthBiasState::default(),
-                }),
-                multisample: wgpu::MultisampleState::default(),
-                multiview: None,
             })
-        };
-
-        // Create a vertex buffer
-        const VERTICES: &[MyVertex] = &[
-            MyVertex {
-                position: [-0.0868241, 0.49240386, 0.0],
-                tex_coord: [0.4131759, 0.00759614],
-            }, // A
-            MyVertex {
-                position: [-0.49513406, 0.06958647, 0.0],
-                tex_coord: [0.0048659444, 0.43041354],
-            }, // B
-            MyVertex {
-                position: [-0.21918549, -0.44939706, 0.0],
-                tex_coord: [0.28081453, 0.949397],
-            }, // C
-            MyVertex {
-                position: [0.35966998, -0.3473291, 0.0],
-                tex_coord: [0.85967, 0.84732914],
-            }, // D
-            MyVertex {
-                position: [0.44147372, 0.2347359, 0.0],
-                tex_coord: [0.9414737, 0.2652641],
-            }, // E
-        ];
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
-            usage: wgpu::BufferUsages::VERTEX,
-            contents: bytemuck::cast_slice(VERTICES),
         });
+        let scene_pipelines = ScenePipelines::new(
+            &device,
+            &mut resources,
+            scene_layout,
+            scene_shader,
+            sample_count,
+            settings.rasterization.primitive_state(polygon_mode_line),
+            settings.depth_prepass,
+            settings.rasterization.wireframe && !polygon_mode_line,
+        );
+        let id_picking = IdPicking::new(
+            &device,
+            &resources[scene_layout],
+            &resources[scene_shader],
+            &[MyVertex::layout(), InstanceRaw::layout()],
+            settings.rasterization.primitive_state(polygon_mode_line),
+            surface_config.width,
+            surface_config.height,
+        );
 
-        const INDICES: &[u16] = &[0, 1, 4, 1, 2, 4, 2, 3, 4];
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
-            usage: wgpu::BufferUsages::INDEX,
-            contents: bytemuck::cast_slice(INDICES),
+        let vertex_buffer = resources.create("vertices", |_, label| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label,
+                usage: wgpu::BufferUsages::VERTEX,
+                contents: bytemuck::cast_slice(VERTICES),
+            })
+        });
+        let index_buffer = resources.create("indices", |_, label| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label,
+                usage: wgpu::BufferUsages::INDEX,
+                contents: bytemuck::cast_slice(INDICES),
+            })
         });
         let num_indices = INDICES.len() as u32;
 
-        let image = image::load_from_memory(include_bytes!("happy-tree.png"))
-            .expect("Image should be loaded");
-        let texture = MyTexture::from_image(&device, &queue, image);
-
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            label: None,
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
+        let texture_paths = options.textures.clone();
+        let images = Self::load_images(&texture_paths)?;
+        let mut texture_layers = 0;
+        let texture = resources.create("texture array", |_, label| {
+            let texture = MyTexture::from_images(&device, &queue, label, &images);
+            texture_layers = texture.layers;
+            texture.view
         });
 
-        let instances = {
-            let mut instances = Vec::new();
-            const N: i32 = 300;
-            for z in -N..=N {
-                for x in -N..=N {
-                    instances.push(Instance { x, z });
-                }
-            }
-            instances
-        };
-
-        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: None,
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-            size: (std::mem::size_of::<InstanceRaw>() * instances.len()) as u64,
-            mapped_at_creation: false,
+        let (instances, chunks) = Instance::grid(settings.grid_size);
+        let instance_grid = InstanceGrid::new(&instances);
+        let instance_buffer = resources.create("instances", |_, label| {
+            Self::create_instance_buffer(&device, label, instances.len())
         });
-
-        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: None,
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            size: std::mem::size_of::<Mat4>() as u64,
-            mapped_at_creation: false,
+        let gpu_sort = compute_shaders.then(|| GpuSort::new(&device, instances.len()));
+
+        let uniform_buffer = resources.create("world uniform", |_, label| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                size: std::mem::size_of::<WorldUniform>() as u64,
+                mapped_at_creation: false,
+            })
         });
 
-        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &uniform_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: uniform_buffer.as_entire_binding(),
-            }],
+        let uniform_bind_group = resources.create("uniforms", |resources, label| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label,
+                layout: &resources[uniform_bind_group_layout],
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: resources[uniform_buffer].as_entire_binding(),
+                }],
+            })
         });
 
-        let texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
-                },
-            ],
+        let sampler = resources.sampler(
+            &device,
+            &MyTexture::sampler_descriptor(&settings.sampler_filters),
+        );
+        let texture_bind_group = resources.create("texture array", |resources, label| {
+            MyTexture::create_bind_group(
+                &device,
+                resources,
+                label,
+                texture_bind_group_layout,
+                texture,
+                sampler,
+            )
         });
 
-        let depth_texture_view = MyTexture::create_depth_texture(&device, &surface_config).view;
+        let transients = RefCell::new(TransientPool::new(
+            surface_config.width,
+            surface_config.height,
+        ));
+        let post_process = PostProcess::new(
+            &device,
+            &queue,
+            surface_config.format.add_srgb_suffix(),
+            surface_config.width,
+            surface_config.height,
+        );
+
+        let oit = WeightedBlendedOit::new(
+            &device,
+            PostProcess::HDR_FORMAT,
+            surface_config.width,
+            surface_config.height,
+            sample_count,
+        );
+
+        let overdraw = Overdraw::new(
+            &device,
+            surface_config.format.add_srgb_suffix(),
+            surface_config.width,
+            surface_config.height,
+            sample_count,
+        );
+
+        let occlusion = OcclusionCulling::new(
+            &device,
+            &resources[uniform_bind_group_layout],
+            PostProcess::HDR_FORMAT,
+            sample_count,
+            chunks,
+        );
+        let pipeline_statistics = PipelineStatistics::new(&device);
 
-        State {
+        let text = TextRenderer::new(
+            &device,
+            &queue,
+            surface_config.format.add_srgb_suffix(),
+            16.0 * window.map_or(1.0, |window| window.scale_factor()) as f32,
+        );
+        let presentation = window.zip(surface).map(|(window, surface)| Presentation {
+            window,
             surface,
+            ui: DebugUi::new(&device, window, surface_config.format.add_srgb_suffix()),
+        });
+
+        Ok(State {
+            presentation,
             device,
             queue,
             surface_config,
-            render_pipeline,
+            present_modes,
+            frame_limiter: FrameLimiter::new(options.max_fps),
+            resources,
+            scene_shader,
+            scene_layout,
+            scene_pipelines,
+            polygon_mode_line,
             vertex_buffer,
             index_buffer,
+            wireframe_buffers: None,
             uniform_buffer,
             uniform_bind_group,
+            texture,
+            texture_layers,
+            texture_paths,
+            texture_bind_group_layout,
             texture_bind_group,
             instances,
+            raws: Vec::new(),
             instance_buffer,
+            sort_order: Vec::new(),
+            gpu_sort,
             num_indices,
-            depth_texture_view,
+            sample_count,
+            transients,
+            post_process,
+            oit,
+            overdraw,
+            prepass_benchmark: None,
+            environment,
+            skybox,
+            occlusion,
+            pipeline_statistics,
+            query_report: QueryReport::new(),
+            text,
+            hud: Hud::new(),
+            settings,
+            screenshot_requested: false,
+            graph_dump_requested: false,
+            view_proj: Mat4::IDENTITY,
+            cursor: Vec2::ZERO,
+            instance_grid,
+            id_picking,
+            picked: None,
             size,
-            window,
-            time: 0.0,
-            instant: Instant::now(),
+            clock: Clock::new(),
             value_d: 10.0,
+        })
+    }
+
+    /// Loads the happy tree followed by the images given on the command line.
+    fn load_images(paths: &[PathBuf]) -> Result<Vec<image::RgbaImage>, Error> {
+        let tree = image::load_from_memory(include_bytes!("happy-tree.png"))?;
+        let mut images = vec![tree.to_rgba8()];
+        for path in paths {
+            match image::open(path) {
+                Ok(image) => images.push(image.to_rgba8()),
+                Err(err) => log::error!("failed to load {}: {}", path.display(), err),
+            }
+        }
+        Ok(images)
+    }
+
+    /// Loads the HDR panorama or the skybox faces given on the command line,
+    /// falling back to a gradient sky.
+    fn load_environment(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        options: &Options,
+    ) -> Environment {
+        if let Some(path) = &options.environment {
+            match ibl::load_equirect(path) {
+                Ok(image) => return Environment::from_equirect(device, queue, &image),
+                Err(err) => {
+                    log::error!("failed to load the environment {}: {}", path.display(), err)
+                }
+            }
+        }
+        let faces = match &options.skybox {
+            Some(path) => skybox::load_faces(path).unwrap_or_else(|err| {
+                log::error!("failed to load the skybox {}: {}", path.display(), err);
+                skybox::gradient_faces(128)
+            }),
+            None => skybox::gradient_faces(128),
+        };
+        Environment::from_faces(device, queue, &faces)
+    }
+
+    /// Saves the next rendered frame as a PNG file.
+    fn capture_screenshot(&mut self) {
+        if cfg!(target_arch = "wasm32") {
+            log::warn!("screenshots are not supported on the web");
+        } else if !self
+            .surface_config
+            .usage
+            .contains(wgpu::TextureUsages::COPY_SRC)
+        {
+            log::warn!("the surface does not support copying for screenshots");
+        } else {
+            self.screenshot_requested = true;
+        }
+    }
+
+    fn cycle_present_mode(&mut self) {
+        self.surface_config.present_mode =
+            pacing::next_present_mode(self.surface_config.present_mode, &self.present_modes);
+        self.configure_surface();
+        log::info!("present mode: {:?}", self.surface_config.present_mode);
+    }
+
+    /// Measures the selected depth pre-pass, or the `Equal` one if it is off.
+    fn start_prepass_benchmark(&mut self) {
+        let restore = self.settings.depth_prepass;
+        let mode = match restore {
+            DepthPrepass::Off => DepthPrepass::Equal,
+            mode => mode,
+        };
+        log::info!("benchmarking the depth pre-pass {:?}", mode);
+        // The first run switches the pre-pass off in `update`, which compares
+        // the settings and recreates the pipelines
+        self.prepass_benchmark = Some(PrepassBenchmark::new(mode, restore, 300));
+    }
+
+    fn cycle_frame_latency(&mut self) {
+        self.surface_config.desired_maximum_frame_latency =
+            self.surface_config.desired_maximum_frame_latency % 3 + 1;
+        self.configure_surface();
+        log::info!(
+            "frame latency: {}",
+            self.surface_config.desired_maximum_frame_latency
+        );
+    }
+
+    /// Falls back to no MSAA if the adapter cannot multisample the scene targets.
+    fn supported_sample_count(adapter: &wgpu::Adapter, sample_count: u32) -> u32 {
+        let supported = [
+            PostProcess::HDR_FORMAT,
+            MyTexture::DEPTH_FORMAT,
+            WeightedBlendedOit::ACCUM_FORMAT,
+            WeightedBlendedOit::REVEALAGE_FORMAT,
+            Overdraw::COUNTER_FORMAT,
+        ]
+        .iter()
+        .all(|&format| {
+            adapter
+                .get_texture_format_features(format)
+                .flags
+                .sample_count_supported(sample_count)
+        });
+        if supported {
+            sample_count
+        } else {
+            log::warn!("{}x MSAA is not supported", sample_count);
+            1
+        }
+    }
+
+    /// Recreates the render targets of the scene with a new size.
+    fn resize_targets(&mut self, width: u32, height: u32) {
+        self.transients.get_mut().resize(width, height);
+        self.post_process.resize(&self.device, width, height);
+        self.oit.resize(&self.device, width, height);
+        self.overdraw.resize(&self.device, width, height);
+        self.id_picking.resize(&self.device, width, height);
+    }
+
+    fn create_instance_buffer(
+        device: &wgpu::Device,
+        label: Option<&str>,
+        num_instances: usize,
+    ) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            size: (std::mem::size_of::<InstanceRaw>() * num_instances.max(1)) as u64,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Recreates the resources that depend on changed settings.
+    fn apply_settings(&mut self, old: &Settings) {
+        if self.settings.grid_size != old.grid_size {
+            let (instances, chunks) = Instance::grid(self.settings.grid_size);
+            let device = &self.device;
+            self.resources.replace(self.instance_buffer, |_, label| {
+                Self::create_instance_buffer(device, label, instances.len())
+            });
+            if let Some(gpu_sort) = &mut self.gpu_sort {
+                gpu_sort.resize(&self.device, instances.len());
+            }
+            self.instance_grid = InstanceGrid::new(&instances);
+            self.picked = None;
+            self.instances = instances;
+            self.occlusion.set_chunks(&self.device, chunks);
+        }
+        if self.settings.material.transparency != old.material.transparency
+            && self.settings.material.transparency.is_blended()
+        {
+            // Sorting breaks the chunk ranges and blended instances do not occlude
+            self.occlusion.enabled = false;
+        }
+        if self.settings.material.transparency == Transparency::AlphaToCoverage
+            && self.scene_pipelines.alpha_to_coverage.is_none()
+        {
+            log::warn!("alpha to coverage needs MSAA, see --msaa");
+            self.settings.material.transparency = Transparency::Cutout;
+        }
+        if self.settings.gpu_sort && self.gpu_sort.is_none() {
+            log::warn!("GPU sorting needs compute shaders");
+            self.settings.gpu_sort = false;
+        }
+        if self.settings.rasterization != old.rasterization
+            || self.settings.depth_prepass != old.depth_prepass
+        {
+            let barycentric_wireframe = self.barycentric_wireframe();
+            let scene_pipelines = ScenePipelines::new(
+                &self.device,
+                &mut self.resources,
+                self.scene_layout,
+                self.scene_shader,
+                self.sample_count,
+                self.settings
+                    .rasterization
+                    .primitive_state(self.polygon_mode_line),
+                self.settings.depth_prepass,
+                barycentric_wireframe,
+            );
+            std::mem::replace(&mut self.scene_pipelines, scene_pipelines)
+                .release(&mut self.resources);
+        }
+        if self.settings.rasterization != old.rasterization {
+            self.id_picking.set_primitive(
+                &self.device,
+                &self.resources[self.scene_layout],
+                &self.resources[self.scene_shader],
+                &[MyVertex::layout(), InstanceRaw::layout()],
+                self.settings
+                    .rasterization
+                    .primitive_state(self.polygon_mode_line),
+            );
+        }
+        match (self.barycentric_wireframe(), self.wireframe_buffers) {
+            (true, None) => self.wireframe_buffers = Some(self.create_wireframe_buffers()),
+            (false, Some((vertices, indices))) => {
+                self.resources.release(vertices);
+                self.resources.release(indices);
+                self.wireframe_buffers = None;
+            }
+            _ => {}
+        }
+        if self.settings.sampler_filters != old.sampler_filters {
+            self.update_texture_bind_group();
+        }
+    }
+
+    /// Every triangle with vertices of its own, indexed in order, so that the
+    /// vertex index tells the corner for barycentric wireframes.
+    fn create_wireframe_buffers(&mut self) -> (Handle<wgpu::Buffer>, Handle<wgpu::Buffer>) {
+        let device = &self.device;
+        let unindexed_vertices: Vec<_> = INDICES.iter().map(|&i| VERTICES[i as usize]).collect();
+        let vertices = self.resources.create("wireframe vertices", |_, label| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label,
+                usage: wgpu::BufferUsages::VERTEX,
+                contents: bytemuck::cast_slice(&unindexed_vertices),
+            })
+        });
+        let sequential_indices: Vec<u16> = (0..INDICES.len() as u16).collect();
+        let indices = self.resources.create("wireframe indices", |_, label| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label,
+                usage: wgpu::BufferUsages::INDEX,
+                contents: bytemuck::cast_slice(&sequential_indices),
+            })
+        });
+        (vertices, indices)
+    }
+
+    /// Rebinds the texture array, with a sampler for the current filters.
+    fn update_texture_bind_group(&mut self) {
+        let sampler = self.resources.sampler(
+            &self.device,
+            &MyTexture::sampler_descriptor(&self.settings.sampler_filters),
+        );
+        let (device, layout, texture) =
+            (&self.device, self.texture_bind_group_layout, self.texture);
+        self.resources
+            .replace(self.texture_bind_group, |resources, label| {
+                MyTexture::create_bind_group(device, resources, label, layout, texture, sampler)
+            });
+    }
+
+    /// Loads the images of the instances again, e.g. after they were edited.
+    fn reload_textures(&mut self) {
+        let images = match Self::load_images(&self.texture_paths) {
+            Ok(images) => images,
+            Err(err) => {
+                log::error!("{}", err);
+                return;
+            }
+        };
+        let (device, queue) = (&self.device, &self.queue);
+        let mut layers = 0;
+        self.resources.replace(self.texture, |_, label| {
+            let texture = MyTexture::from_images(device, queue, label, &images);
+            layers = texture.layers;
+            texture.view
+        });
+        self.texture_layers = layers;
+        self.update_texture_bind_group();
+        log::info!("reloaded {} images", images.len());
+    }
+
+    /// Highlights the instance under the cursor; with the ID buffer once it
+    /// has been read back.
+    fn pick(&mut self) {
+        if self.settings.picking == Picking::IdBuffer {
+            self.id_picking.request(self.cursor);
+            return;
+        }
+
+        let ray = Ray::from_cursor(
+            self.cursor,
+            Vec2::new(self.size.width as f32, self.size.height as f32),
+            self.view_proj.inverse(),
+        );
+        self.picked = self.instance_grid.cast(&ray, &self.raws).map(|hit| {
+            let instance = self.instances[hit.index];
+            log::info!(
+                "picked instance x {} z {} at distance {:.1}",
+                instance.x,
+                instance.z,
+                hit.distance
+            );
+            hit.index
+        });
+        if self.picked.is_none() {
+            log::info!("nothing picked");
+        }
+    }
+
+    /// Applies `surface_config` to the surface of the window.
+    fn configure_surface(&self) {
+        if let Some(presentation) = &self.presentation {
+            presentation
+                .surface
+                .configure(&self.device, &self.surface_config);
         }
     }
 
@@ -468,40 +1424,141 @@ impl<'w> State<'w> {
         self.size = new_size;
         self.surface_config.width = new_size.width;
         self.surface_config.height = new_size.height;
-        self.surface.configure(&self.device, &self.surface_config);
-        self.window.request_redraw();
-        self.depth_texture_view =
-            MyTexture::create_depth_texture(&self.device, &self.surface_config).view;
+        self.configure_surface();
+        if let Some(presentation) = &self.presentation {
+            presentation.window.request_redraw();
+        }
+        self.resize_targets(new_size.width, new_size.height);
     }
 
     fn update(&mut self) {
-        self.time += self.instant.elapsed().as_secs_f32();
-        self.instant = Instant::now();
+        self.clock.tick();
+        self.hud.update(self.clock.frame_time());
+
+        let old_settings = self.settings;
+        if let Some(presentation) = &mut self.presentation {
+            presentation
+                .ui
+                .run(presentation.window, &mut self.settings, &mut self.value_d);
+        }
+        if let Some(benchmark) = &mut self.prepass_benchmark {
+            match benchmark.record(self.clock.frame_time()) {
+                Some(depth_prepass) => self.settings.depth_prepass = depth_prepass,
+                None => {
+                    self.settings.depth_prepass = benchmark.restore();
+                    self.prepass_benchmark = None;
+                }
+            }
+        }
+        self.apply_settings(&old_settings);
+
+        self.update_scene();
+    }
+
+    /// Animates the scene to the current clock time.
+    fn update_scene(&mut self) {
+        let time = self.clock.time();
+        let d = self.value_d;
+        let theta = time * 0.1;
+        let phi = ((time * 0.7).cos() + 1.0) * std::f32::consts::PI / 8.0;
+        let eye = vec3(
+            theta.cos() * phi.cos() * d,
+            phi.sin() * d,
+            theta.sin() * phi.cos() * d,
+        );
+
+        // Collect query results of previous frames
+        self.device.poll(wgpu::Maintain::Poll);
+        if let Some(statistics) = &mut self.pipeline_statistics {
+            statistics.prepare();
+        }
+        self.query_report.log(
+            &self.occlusion,
+            self.pipeline_statistics.as_ref(),
+            self.size.width * self.size.height,
+        );
+        if let Some(hit) = self.id_picking.try_read() {
+            // The grid may have changed since
+            self.picked = hit
+                .filter(|hit| hit.index < self.instances.len())
+                .map(|hit| {
+                    let instance = self.instances[hit.index];
+                    log::info!(
+                        "picked instance x {} z {} at depth {:.6} ({:.1}, {:.1}, {:.1})",
+                        instance.x,
+                        instance.z,
+                        hit.depth,
+                        hit.position.x,
+                        hit.position.y,
+                        hit.position.z
+                    );
+                    hit.index
+                });
+            if self.picked.is_none() {
+                log::info!("nothing picked");
+            }
+        }
 
         {
-            let raws: Vec<_> = self
-                .instances
-                .iter()
-                .map(|inst| inst.to_raw(self.time))
-                .collect();
-            self.queue
-                .write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&raws))
-        }
-
-        let view = {
-            let d = self.value_d;
-            let theta = self.time * 0.1;
-            let phi = ((self.time * 0.7).cos() + 1.0) * std::f32::consts::PI / 8.0;
-            glam::Mat4::look_at_lh(
-                vec3(
-                    theta.cos() * phi.cos() * d,
-                    phi.sin() * d,
-                    theta.sin() * phi.cos() * d,
-                ),
-                vec3(0., 0., 0.),
-                vec3(0., 1., 0.),
-            )
-        };
+            self.raws.clear();
+            self.raws
+                .extend(self.instances.iter().enumerate().map(|(i, inst)| {
+                    inst.to_raw(
+                        time,
+                        &self.settings.wave,
+                        &self.settings.instance_style,
+                        self.texture_layers,
+                        self.picked == Some(i),
+                    )
+                }));
+            let raws = &self.raws;
+            self.occlusion.prepare(&self.queue, raws, eye);
+            self.sort_order.clear();
+            match (self.settings.material.transparency, self.sorting_on_gpu()) {
+                (Transparency::Sorted, Some(gpu_sort)) => {
+                    self.queue
+                        .write_buffer(gpu_sort.input(), 0, bytemuck::cast_slice(raws));
+                    gpu_sort.prepare(&self.queue, eye);
+                }
+                (Transparency::Sorted, None) => {
+                    self.sort_order = sort::back_to_front(raws, eye);
+                    let sorted: Vec<_> =
+                        self.sort_order.iter().map(|&i| raws[i as usize]).collect();
+                    self.queue.write_buffer(
+                        &self.resources[self.instance_buffer],
+                        0,
+                        bytemuck::cast_slice(&sorted),
+                    );
+                }
+                _ => {
+                    self.queue.write_buffer(
+                        &self.resources[self.instance_buffer],
+                        0,
+                        bytemuck::cast_slice(raws),
+                    );
+                }
+            }
+        }
+
+        self.hud.queue(
+            &mut self.text,
+            &HudInfo {
+                clock: &self.clock,
+                present_mode: self.surface_config.present_mode,
+                frame_latency: self.surface_config.desired_maximum_frame_latency,
+                max_fps: self.frame_limiter.max_fps,
+                instances: self.instances.len(),
+                distance: d,
+                theta,
+                phi,
+                eye,
+                picked: self
+                    .picked
+                    .map(|i| (self.instances[i].x, self.instances[i].z)),
+            },
+        );
+
+        let view = glam::Mat4::look_at_lh(eye, vec3(0., 0., 0.), vec3(0., 1., 0.));
         let projection = Mat4::perspective_lh(
             90.0,
             self.size.width as f32 / self.size.height as f32,
@@ -509,116 +1566,639 @@ impl<'w> State<'w> {
             1000.0,
         );
         let view_proj = projection * view;
+        self.view_proj = view_proj;
         self.queue.write_buffer(
-            &self.uniform_buffer,
+            &self.resources[self.uniform_buffer],
             0,
-            bytemuck::cast_slice(&view_proj.to_cols_array()),
-        )
+            bytemuck::bytes_of(&WorldUniform {
+                view_proj: view_proj.to_cols_array(),
+                eye: eye.to_array(),
+                reflectivity: self.settings.reflectivity,
+                roughness: self.settings.roughness,
+                ibl: self.settings.ibl as u32,
+                alpha_cutoff: self.settings.material.shader_alpha_cutoff(),
+                wireframe: self.barycentric_wireframe() as u32,
+            }),
+        );
+        self.skybox.prepare(&self.queue, view, projection);
+        self.overdraw
+            .prepare(&self.queue, self.settings.overdraw_max_count);
+        self.post_process.prepare(
+            &self.queue,
+            &self.settings.post_processing,
+            self.settings.tonemapping,
+            self.settings.exposure,
+        );
     }
 
-    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let surface_texture = self.surface.get_current_texture()?;
-        let surface_texture_view =
-            surface_texture
-                .texture
-                .create_view(&wgpu::TextureViewDescriptor {
-                    format: Some(self.surface_config.format.add_srgb_suffix()),
-                    ..Default::default()
+    /// Whether wireframes have to be drawn by the fragment shader, without
+    /// line rasterization.
+    fn barycentric_wireframe(&self) -> bool {
+        self.settings.rasterization.wireframe && !self.polygon_mode_line
+    }
+
+    /// The GPU sort, if it is used for the current settings.
+    fn sorting_on_gpu(&self) -> Option<&GpuSort> {
+        self.gpu_sort.as_ref().filter(|_| self.settings.gpu_sort)
+    }
+
+    /// Adds the passes drawing the instanced scene into the HDR target and
+    /// post-processing it onto `output`.
+    fn add_scene_passes<'a>(&'a self, graph: &mut RenderGraph<'a>, output: Resource) {
+        let transparency = self.settings.material.transparency;
+        let depth_prepass = self.scene_pipelines.depth_prepass(transparency);
+
+        let scene = graph.import_view("scene", self.post_process.scene_view());
+        let depth_desc = TextureDesc {
+            format: MyTexture::DEPTH_FORMAT,
+            sample_count: self.sample_count,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        };
+        let depth = graph.transient("depth", depth_desc);
+        let msaa = (self.sample_count > 1).then(|| {
+            graph.transient(
+                "msaa color",
+                TextureDesc {
+                    format: PostProcess::HDR_FORMAT,
+                    sample_count: self.sample_count,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                },
+            )
+        });
+        let sorted = graph.import("sorted instances");
+        let queries = graph.import("queries");
+
+        let sorted_instances = match (transparency, self.sorting_on_gpu()) {
+            (Transparency::Sorted, Some(gpu_sort)) => {
+                graph.add_pass("gpu sort", &[], &[sorted], move |encoder, _| {
+                    gpu_sort.encode(encoder)
                 });
+                gpu_sort.output()
+            }
+            _ => &self.resources[self.instance_buffer],
+        };
 
-        let mut command_encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        if let Some(depth_only) = depth_prepass {
+            graph.add_pass(
+                "depth pre-pass",
+                &[],
+                &[depth],
+                move |encoder, resources| {
+                    let mut render_pass = Self::begin_depth_prepass(encoder, resources.view(depth));
+                    render_pass.set_pipeline(&self.resources[depth_only]);
+                    self.bind_instances(&mut render_pass, &self.resources[self.instance_buffer]);
+                    self.draw_unqueried(&mut render_pass);
+                },
+            );
+        }
 
-        {
-            let mut render_pass = command_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: None,
+        let mut reads = vec![sorted];
+        if depth_prepass.is_some() {
+            reads.push(depth);
+        }
+        let mut writes = vec![scene, depth, queries];
+        writes.extend(msaa);
+        graph.add_pass("scene", &reads, &writes, move |encoder, resources| {
+            let (view, resolve_target) = match msaa {
+                Some(msaa) => (resources.view(msaa), Some(resources.view(scene))),
+                None => (resources.view(scene), None),
+            };
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("scene"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &surface_texture_view,
-                    resolve_target: None, // for MSAA
+                    view,
+                    resolve_target,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.06,
-                            b: 0.1,
+                            r: self.settings.clear_color[0] as f64,
+                            g: self.settings.clear_color[1] as f64,
+                            b: self.settings.clear_color[2] as f64,
                             a: 1.0,
                         }),
                         store: wgpu::StoreOp::Store,
                     },
                 })],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture_view,
+                    view: resources.view(depth),
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        load: if depth_prepass.is_some() {
+                            wgpu::LoadOp::Load
+                        } else {
+                            wgpu::LoadOp::Clear(1.0)
+                        },
                         store: wgpu::StoreOp::Store,
                     }),
                     stencil_ops: None,
                 }),
-                occlusion_query_set: None,
+                occlusion_query_set: self.occlusion.query_set(),
                 timestamp_writes: None,
             });
 
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.texture_bind_group, &[]);
+            if let Some(statistics) = &self.pipeline_statistics {
+                statistics.begin(&mut render_pass);
+            }
+
+            if !transparency.is_blended() {
+                render_pass.set_pipeline(&self.resources[self.scene_pipelines.get(transparency)]);
+                self.bind_instances(&mut render_pass, &self.resources[self.instance_buffer]);
+                if self.occlusion.enabled {
+                    self.occlusion
+                        .draw_visible(&mut render_pass, self.num_indices);
+                    self.occlusion
+                        .draw_proxies(&mut render_pass, &self.resources[self.uniform_bind_group]);
+                } else {
+                    render_pass.draw_indexed(
+                        0..self.num_indices,
+                        0,
+                        0..self.instances.len() as u32,
+                    );
+                }
+            }
+
+            if self.settings.skybox {
+                self.skybox.draw(&mut render_pass, &self.environment);
+            }
+
+            // Blended instances come last, over the opaque scene and the sky
+            if transparency == Transparency::Sorted {
+                render_pass.set_pipeline(&self.resources[self.scene_pipelines.get(transparency)]);
+                self.bind_instances(&mut render_pass, sorted_instances);
+                render_pass.draw_indexed(0..self.num_indices, 0, 0..self.instances.len() as u32);
+            }
+
+            if let Some(statistics) = &self.pipeline_statistics {
+                statistics.end(&mut render_pass);
+            }
+        });
+
+        if transparency == Transparency::WeightedBlended {
+            let accumulation = graph.import("oit accumulation");
+            graph.add_pass(
+                "oit accumulate",
+                &[depth],
+                &[accumulation],
+                move |encoder, resources| {
+                    let mut render_pass = self.oit.accumulate(encoder, resources.view(depth));
+                    render_pass
+                        .set_pipeline(&self.resources[self.scene_pipelines.weighted_blended]);
+                    self.bind_instances(&mut render_pass, &self.resources[self.instance_buffer]);
+                    render_pass.draw_indexed(
+                        0..self.num_indices,
+                        0,
+                        0..self.instances.len() as u32,
+                    );
+                },
+            );
+            graph.add_pass(
+                "oit composite",
+                &[accumulation, scene],
+                &[scene],
+                move |encoder, resources| self.oit.composite(encoder, resources.view(scene)),
+            );
+        }
+
+        let counts = graph.import("overdraw counts");
+        if self.settings.overdraw {
+            // Apart from the scene depth, which blended instances do not write
+            let overdraw_depth = graph.transient("overdraw depth", depth_desc);
+            if let Some(depth_only) = depth_prepass {
+                graph.add_pass(
+                    "overdraw pre-pass",
+                    &[],
+                    &[overdraw_depth],
+                    move |encoder, resources| {
+                        let mut render_pass =
+                            Self::begin_depth_prepass(encoder, resources.view(overdraw_depth));
+                        render_pass.set_pipeline(&self.resources[depth_only]);
+                        self.bind_instances(
+                            &mut render_pass,
+                            &self.resources[self.instance_buffer],
+                        );
+                        self.draw_unqueried(&mut render_pass);
+                    },
+                );
+            }
+            let mut reads = vec![sorted];
+            if depth_prepass.is_some() {
+                reads.push(overdraw_depth);
+            }
+            graph.add_pass(
+                "overdraw count",
+                &reads,
+                &[counts, overdraw_depth],
+                move |encoder, resources| {
+                    let mut render_pass = self.overdraw.count(
+                        encoder,
+                        resources.view(overdraw_depth),
+                        depth_prepass.is_some(),
+                    );
+                    if transparency == Transparency::Sorted {
+                        self.bind_instances(&mut render_pass, sorted_instances);
+                    } else {
+                        self.bind_instances(
+                            &mut render_pass,
+                            &self.resources[self.instance_buffer],
+                        );
+                    }
+                    render_pass
+                        .set_pipeline(&self.resources[self.scene_pipelines.overdraw(transparency)]);
+                    self.draw_unqueried(&mut render_pass);
+                },
+            );
+        }
+
+        let query_results = graph.import("query results");
+        graph.add_pass(
+            "resolve queries",
+            &[queries],
+            &[query_results],
+            move |encoder, _| {
+                self.occlusion.resolve(encoder);
+                if let Some(statistics) = &self.pipeline_statistics {
+                    statistics.resolve(encoder);
+                }
+            },
+        );
+
+        graph.add_pass(
+            "post-process",
+            &[scene],
+            &[output],
+            move |encoder, resources| self.post_process.render(encoder, resources.view(output)),
+        );
+        if self.settings.overdraw {
+            graph.add_pass(
+                "overdraw heat map",
+                &[counts, output],
+                &[output],
+                move |encoder, resources| self.overdraw.render(encoder, resources.view(output)),
+            );
+        }
+    }
+
+    /// Adds the passes drawing all instances into the ID buffer, from the
+    /// unsorted instances where the GPU sorts them, and copying the pixel
+    /// under the cursor.
+    fn add_id_picking_passes<'a>(&'a self, graph: &mut RenderGraph<'a>) {
+        let instance_buffer = match (self.settings.material.transparency, self.sorting_on_gpu()) {
+            (Transparency::Sorted, Some(gpu_sort)) => gpu_sort.input(),
+            _ => &self.resources[self.instance_buffer],
+        };
+        let ids = graph.import("ids");
+        let readback = graph.import("pick readback");
+        graph.add_pass("id buffer", &[], &[ids], move |encoder, _| {
+            let mut render_pass = self.id_picking.begin(encoder);
+            self.bind_instances(&mut render_pass, instance_buffer);
+            render_pass.draw_indexed(0..self.num_indices, 0, 0..self.instances.len() as u32);
+        });
+        graph.add_pass("pick copy", &[ids], &[readback], move |encoder, _| {
+            self.id_picking.copy(encoder)
+        });
+    }
+
+    /// Begins a pass filling `depth_view` without shading, for the color
+    /// pass to shade only the visible fragments.
+    fn begin_depth_prepass<'a>(
+        command_encoder: &'a mut wgpu::CommandEncoder,
+        depth_view: &'a wgpu::TextureView,
+    ) -> wgpu::RenderPass<'a> {
+        command_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("depth pre-pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        })
+    }
+
+    /// Draws the instances left by occlusion culling, without queries.
+    fn draw_unqueried(&self, render_pass: &mut wgpu::RenderPass) {
+        if self.occlusion.enabled {
+            for range in self.occlusion.visible_ranges() {
+                render_pass.draw_indexed(0..self.num_indices, 0, range);
+            }
+        } else {
             render_pass.draw_indexed(0..self.num_indices, 0, 0..self.instances.len() as u32);
         }
+    }
+
+    fn bind_instances<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        instance_buffer: &'a wgpu::Buffer,
+    ) {
+        let (vertex_buffer, index_buffer) = match self.wireframe_buffers {
+            Some(wireframe_buffers) if self.barycentric_wireframe() => wireframe_buffers,
+            _ => (self.vertex_buffer, self.index_buffer),
+        };
+        let resources = &self.resources;
+        render_pass.set_vertex_buffer(0, resources[vertex_buffer].slice(..));
+        render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        render_pass.set_index_buffer(resources[index_buffer].slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.set_bind_group(0, &resources[self.uniform_bind_group], &[]);
+        render_pass.set_bind_group(1, &resources[self.texture_bind_group], &[]);
+        render_pass.set_bind_group(2, self.environment.bind_group(), &[]);
+    }
+
+    /// Draws and presents a frame to the window, see [`State::record`]
+    /// without one.
+    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        let Some(presentation) = &mut self.presentation else {
+            return Ok(());
+        };
+        // Before acquiring the surface, so that text and UI queued for a
+        // frame that is skipped are not drawn with the next one
+        let screen_size = [self.size.width, self.size.height];
+        self.text.prepare(&self.device, &self.queue, screen_size);
+        presentation
+            .ui
+            .prepare(&self.device, &self.queue, screen_size);
+
+        let surface_texture = presentation.surface.get_current_texture()?;
+        let surface_texture_view =
+            surface_texture
+                .texture
+                .create_view(&wgpu::TextureViewDescriptor {
+                    format: Some(self.surface_config.format.add_srgb_suffix()),
+                    ..Default::default()
+                });
+
+        let mut command_encoder =
+            self.device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("frame"),
+                });
+
+        let id_picking = self.id_picking.is_requested();
+        let dump_graph = std::mem::take(&mut self.graph_dump_requested);
+        #[cfg(not(target_arch = "wasm32"))]
+        let screenshot_requested = std::mem::take(&mut self.screenshot_requested);
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut screenshot = None;
+
+        {
+            let mut graph = RenderGraph::new();
+            let surface = graph.import_view("surface", &surface_texture_view);
+            self.add_scene_passes(&mut graph, surface);
+            if id_picking {
+                self.add_id_picking_passes(&mut graph);
+            }
+            graph.add_pass("text", &[surface], &[surface], |encoder, resources| {
+                self.text.render(encoder, resources.view(surface))
+            });
+            if let Some(presentation) = &self.presentation {
+                graph.add_pass("ui", &[surface], &[surface], |encoder, resources| {
+                    presentation.ui.render(encoder, resources.view(surface))
+                });
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            if screenshot_requested {
+                graph.add_pass("screenshot", &[surface], &[], |encoder, _| {
+                    screenshot = Some(screenshot::TextureReadback::encode(
+                        &self.device,
+                        encoder,
+                        &surface_texture.texture,
+                    ));
+                });
+            }
+
+            if dump_graph {
+                log::info!("{}", graph.dump());
+                log::info!("{}", self.resources.dump());
+            }
+            graph.execute(
+                &self.device,
+                &mut self.transients.borrow_mut(),
+                &mut command_encoder,
+            );
+        }
 
         self.queue.submit(std::iter::once(command_encoder.finish()));
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(screenshot) = screenshot {
+            match screenshot.read(&self.device) {
+                Ok(image) => match screenshot::save_timestamped(&image, "screenshot") {
+                    Ok(path) => log::info!("saved {}", path.display()),
+                    Err(err) => log::error!("failed to save screenshot: {}", err),
+                },
+                Err(err) => log::error!("screenshot failed: {}", err),
+            }
+        }
+
         surface_texture.present();
 
+        if id_picking {
+            self.id_picking
+                .map(self.view_proj, std::mem::take(&mut self.sort_order));
+        }
+
+        self.occlusion.after_submit();
+        if let Some(statistics) = &mut self.pipeline_statistics {
+            statistics.after_submit();
+        }
+
         Ok(())
     }
-}
 
-pub async fn run(event_loop: event_loop::EventLoop<()>, window: Window) {
-    let mut state = State::new(&window).await;
+    /// Renders a frame sequence offscreen with a fixed timestep.
+    ///
+    /// Everything that depends on the wall clock or on asynchronous readbacks
+    /// (the HUD, occlusion culling and pipeline statistics) is disabled, so
+    /// that the output is reproducible.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn record(&mut self, options: &recording::RecordingOptions) -> std::io::Result<()> {
+        let mut recorder = recording::Recorder::new(
+            &self.device,
+            self.surface_config.format.add_srgb_suffix(),
+            options,
+        )?;
+
+        self.hud.visible = false;
+        self.occlusion.enabled = false;
+        self.pipeline_statistics = None;
+        self.size = winit::dpi::PhysicalSize::new(options.width, options.height);
+        self.resize_targets(options.width, options.height);
+
+        for frame in 0..options.frames {
+            self.clock.set_time(frame as f32 / options.fps as f32);
+            self.update_scene();
+
+            let mut command_encoder =
+                self.device
+                    .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                        label: Some("recording frame"),
+                    });
+            let mut graph = RenderGraph::new();
+            let output = graph.import_view("recording", &recorder.view);
+            self.add_scene_passes(&mut graph, output);
+            graph.execute(
+                &self.device,
+                &mut self.transients.borrow_mut(),
+                &mut command_encoder,
+            );
+            // After all passes of the graph
+            let readback = screenshot::TextureReadback::encode(
+                &self.device,
+                &mut command_encoder,
+                &recorder.texture,
+            );
+            self.queue.submit(std::iter::once(command_encoder.finish()));
+
+            let image = readback.read(&self.device).map_err(std::io::Error::other)?;
+            recorder.write_frame(&image)?;
+        }
+
+        recorder.finish()
+    }
+}
 
-    event_loop
-        .run(move |event, target| match event {
-            Event::WindowEvent { event, window_id } if window_id == state.window.id() => {
-                match event {
-                    WindowEvent::Resized(physical_size) => {
-                        state.resize(physical_size);
+pub async fn run(
+    event_loop: event_loop::EventLoop<()>,
+    window: Window,
+    options: Options,
+) -> Result<(), Error> {
+    let mut state = State::new(Some(&window), &options).await?;
+
+    // Set by failures that end the event loop
+    let mut fatal = None;
+    let failure = &mut fatal;
+    let window = &window;
+    event_loop.run(move |event, target| match event {
+        Event::WindowEvent { event, window_id } if window_id == window.id() => {
+            if let Some(presentation) = &mut state.presentation {
+                if presentation.ui.on_window_event(window, &event) {
+                    return;
+                }
+            }
+            match event {
+                WindowEvent::Resized(physical_size) => {
+                    state.resize(physical_size);
+                }
+                WindowEvent::CloseRequested => {
+                    target.exit();
+                }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            state: ElementState::Pressed,
+                            logical_key,
+                            ..
+                        },
+                    ..
+                } => {
+                    if state.clock.handle_key(&logical_key) {
+                        return;
                     }
-                    WindowEvent::CloseRequested => {
-                        target.exit();
+                    match logical_key.as_ref() {
+                        Key::Character("o") => {
+                            if state.settings.material.transparency.is_blended() {
+                                log::warn!("occlusion culling needs opaque instances");
+                                return;
+                            }
+                            state.occlusion.enabled = !state.occlusion.enabled;
+                            log::info!("occlusion culling: {}", state.occlusion.enabled);
+                        }
+                        Key::Character("h") => state.hud.visible = !state.hud.visible,
+                        Key::Character("u") => {
+                            if let Some(presentation) = &mut state.presentation {
+                                presentation.ui.visible = !presentation.ui.visible;
+                            }
+                        }
+                        Key::Character("p") => state.capture_screenshot(),
+                        Key::Character("v") => state.cycle_present_mode(),
+                        Key::Character("l") => state.cycle_frame_latency(),
+                        Key::Character("b") => state.start_prepass_benchmark(),
+                        Key::Character("g") => state.graph_dump_requested = true,
+                        Key::Character("r") => state.reload_textures(),
+                        Key::Character("m") => {
+                            state.frame_limiter.cycle();
+                            log::info!("frame rate cap: {:?}", state.frame_limiter.max_fps);
+                        }
+                        _ => {}
+                    }
+                }
+                WindowEvent::CursorMoved { position, .. } => {
+                    state.cursor = Vec2::new(position.x as f32, position.y as f32);
+                }
+                WindowEvent::MouseInput {
+                    state: ElementState::Pressed,
+                    button: MouseButton::Left,
+                    ..
+                } => state.pick(),
+                WindowEvent::MouseWheel { delta, .. } => match delta {
+                    MouseScrollDelta::PixelDelta(pos) => {
+                        state.value_d += (pos.y / 20.0) as f32;
+                        state.value_d = state.value_d.max(0.1);
                     }
-                    WindowEvent::MouseWheel { delta, .. } => match delta {
-                        MouseScrollDelta::PixelDelta(pos) => {
-                            state.value_d += (pos.y / 20.0) as f32;
-                            state.value_d = state.value_d.max(0.1);
+                    MouseScrollDelta::LineDelta(_, _) => {}
+                },
+                WindowEvent::RedrawRequested => {
+                    state.update();
+                    match state.render() {
+                        Ok(_) => {}
+                        Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
+                        Err(wgpu::SurfaceError::OutOfMemory) => {
+                            // On the web `run` does not return to report it
+                            #[cfg(target_arch = "wasm32")]
+                            show_error(&Error::OutOfMemory);
+                            *failure = Some(Error::OutOfMemory);
+                            target.exit();
+                            return;
                         }
-                        MouseScrollDelta::LineDelta(_, _) => {}
-                    },
-                    WindowEvent::RedrawRequested => {
-                        state.update();
-                        match state.render() {
-                            Ok(_) => {}
-                            Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
-                            Err(wgpu::SurfaceError::OutOfMemory) => panic!("Out of memory"),
-                            Err(err) => log::warn!("{:?}", err),
+                        Err(err) => log::warn!("{:?}", err),
+                    }
+                    match state.frame_limiter.frame_presented() {
+                        Some(wait) => target.set_control_flow(ControlFlow::wait_duration(wait)),
+                        None => {
+                            target.set_control_flow(ControlFlow::Wait);
+                            window.request_redraw();
                         }
-                        state.window.request_redraw();
                     }
-                    _ => {}
                 }
+                _ => {}
             }
-            _ => {}
-        })
-        .expect("event loop failed");
+        }
+        Event::NewEvents(StartCause::ResumeTimeReached { .. }) => {
+            window.request_redraw();
+        }
+        _ => {}
+    })?;
+    fatal.map_or(Ok(()), Err)
 }
 
-pub fn prepare_window() -> (EventLoop<()>, Window) {
-    let event_loop = EventLoop::new().unwrap();
+/// Renders the frames of `--record` offscreen, without a window or surface.
+///
+/// Must only be called with [`Options::recording`] set.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn record(options: Options) -> Result<(), Error> {
+    let recording = options
+        .recording
+        .as_ref()
+        .expect("recording options are set");
+    let mut state = State::new(None, &options).await?;
+    state.record(recording).map_err(Error::Recording)
+}
+
+/// Prints the adapters for `--list-adapters`.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn list_adapters(options: &Options) {
+    print!("{}", adapter::report(options));
+}
+
+pub fn prepare_window() -> Result<(EventLoop<()>, Window), Error> {
+    let event_loop = EventLoop::new()?;
     let window = WindowBuilder::new()
         .with_title("winit window")
-        .build(&event_loop)
-        .unwrap();
-    (event_loop, window)
+        .build(&event_loop)?;
+    Ok((event_loop, window))
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -626,23 +2206,53 @@ pub fn prepare_window() -> (EventLoop<()>, Window) {
 pub fn wasm_main() {
     // Send logs to the web console.
     console_error_panic_hook::set_once();
-    console_log::init_with_level(log::Level::Warn).expect("Couldn't initialize logger");
+    if let Err(err) = console_log::init_with_level(log::Level::Warn) {
+        return show_error(&Error::Logger(err));
+    }
 
-    let (event_loop, window) = prepare_window();
+    let (event_loop, window) = match prepare_window() {
+        Ok(prepared) => prepared,
+        Err(err) => return show_error(&err),
+    };
 
     // Append the canvas to the document body.
     {
         use winit::platform::web::WindowExtWebSys;
-        web_sys::window()
+        let appended = web_sys::window()
             .and_then(|win| win.document())
             .and_then(|doc| {
                 let dst = doc.get_element_by_id("wasm-example")?;
-                let canvas = web_sys::Element::from(window.canvas().unwrap());
+                let canvas = web_sys::Element::from(window.canvas()?);
                 dst.append_child(&canvas).ok()?;
                 Some(())
-            })
-            .expect("Couldn't append canvas to document body.");
+            });
+        if appended.is_none() {
+            return show_error(&Error::Canvas);
+        }
     }
 
-    wasm_bindgen_futures::spawn_local(run(event_loop, window));
+    wasm_bindgen_futures::spawn_local(async {
+        if let Err(err) = run(event_loop, window, Options::default()).await {
+            show_error(&err);
+        }
+    });
+}
+
+/// Shows `err` in the page, where the canvas would be.
+#[cfg(target_arch = "wasm32")]
+fn show_error(err: &Error) {
+    log::error!("{}", err);
+    let shown = web_sys::window()
+        .and_then(|win| win.document())
+        .and_then(|doc| {
+            let dst = doc.get_element_by_id("wasm-example")?;
+            let message = doc.create_element("p").ok()?;
+            message.set_class_name("error");
+            message.set_text_content(Some(&format!("error: {}", err)));
+            dst.append_child(&message).ok()?;
+            Some(())
+        });
+    if shown.is_none() {
+        log::error!("cannot show the error in the page");
+    }
 }
@@ -1,5 +1,29 @@
 pub fn main() {
     env_logger::init();
-    let (event_loop, window) = tutorial8::prepare_window();
-    pollster::block_on(tutorial8::run(event_loop, window));
+    let options = match tutorial8::Options::parse(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", tutorial8::Options::USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, tutorial8::Options::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if options.list_adapters {
+        tutorial8::list_adapters(&options);
+        return;
+    }
+    let result = if options.recording.is_some() {
+        pollster::block_on(tutorial8::record(options))
+    } else {
+        tutorial8::prepare_window().and_then(|(event_loop, window)| {
+            pollster::block_on(tutorial8::run(event_loop, window, options))
+        })
+    };
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
 }
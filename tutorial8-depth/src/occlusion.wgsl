@@ -0,0 +1,38 @@
+// Bounding boxes of instance chunks for occlusion queries
+
+struct WorldUniform {
+    view_proj: mat4x4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> world: WorldUniform;
+
+struct BoundsInput {
+    @location(0) min: vec3<f32>,
+    @location(1) max: vec3<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, bounds: BoundsInput) -> @builtin(position) vec4<f32> {
+    // Corners are numbered by their (x, y, z) bits
+    var corners = array<u32, 36>(
+        0u, 2u, 6u, 0u, 6u, 4u, // -x
+        1u, 5u, 7u, 1u, 7u, 3u, // +x
+        0u, 4u, 5u, 0u, 5u, 1u, // -y
+        2u, 3u, 7u, 2u, 7u, 6u, // +y
+        0u, 1u, 3u, 0u, 3u, 2u, // -z
+        4u, 6u, 7u, 4u, 7u, 5u, // +z
+    );
+    let corner = corners[index];
+    let position = select(bounds.min, bounds.max, vec3<bool>(
+        (corner & 1u) != 0u,
+        (corner & 2u) != 0u,
+        (corner & 4u) != 0u,
+    ));
+    return world.view_proj * vec4<f32>(position, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(0.0);
+}
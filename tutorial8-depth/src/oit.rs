@@ -0,0 +1,265 @@
+/// Weighted blended order-independent transparency (McGuire and Bavoil, 2013).
+///
+/// Transparent fragments are summed into an accumulation target weighted by
+/// their depth, while the revealage target keeps the product of their
+/// transmittance. A full-screen pass then blends the weighted average color
+/// over the scene, so no sorting is needed.
+pub struct WeightedBlendedOit {
+    layout: wgpu::BindGroupLayout,
+    composite_pipeline: wgpu::RenderPipeline,
+    sample_count: u32,
+    accum: Target,
+    revealage: Target,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Rendered to at the sample count of the scene and resolved for the composite.
+struct Target {
+    view: wgpu::TextureView,
+    multisampled: Option<wgpu::TextureView>,
+}
+
+impl Target {
+    fn attachment(
+        &self,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) -> Option<wgpu::RenderPassColorAttachment<'_>> {
+        Some(wgpu::RenderPassColorAttachment {
+            view: self.multisampled.as_ref().unwrap_or(&self.view),
+            resolve_target: self.multisampled.as_ref().map(|_| &self.view),
+            ops: wgpu::Operations {
+                load,
+                store: wgpu::StoreOp::Store,
+            },
+        })
+    }
+}
+
+impl WeightedBlendedOit {
+    pub const ACCUM_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    pub const REVEALAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+    /// Color targets of the pipelines drawing the transparent geometry, in
+    /// the order of the fragment outputs.
+    pub fn accumulation_targets() -> [Option<wgpu::ColorTargetState>; 2] {
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let transmittance = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Zero,
+            dst_factor: wgpu::BlendFactor::OneMinusSrc,
+            operation: wgpu::BlendOperation::Add,
+        };
+        [
+            Some(wgpu::ColorTargetState {
+                format: Self::ACCUM_FORMAT,
+                blend: Some(wgpu::BlendState {
+                    color: additive,
+                    alpha: additive,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            }),
+            Some(wgpu::ColorTargetState {
+                format: Self::REVEALAGE_FORMAT,
+                blend: Some(wgpu::BlendState {
+                    color: transmittance,
+                    alpha: transmittance,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            }),
+        ]
+    }
+
+    /// `sample_count` has to match the depth buffer of the scene.
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        // Binding 1 is the sampler of fullscreen.wgsl, unused here
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("oit composite"),
+            entries: &[texture_entry(0), texture_entry(2)],
+        });
+
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("oit composite"),
+            source: wgpu::ShaderSource::Wgsl(
+                format!(
+                    "{}\n{}",
+                    include_str!("fullscreen.wgsl"),
+                    include_str!("oit.wgsl")
+                )
+                .into(),
+            ),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("oit composite"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let composite_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("oit composite"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader_module,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader_module,
+                entry_point: "fs_composite",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let (accum, revealage, bind_group) =
+            Self::create_targets(device, &layout, width, height, sample_count);
+
+        Self {
+            layout,
+            composite_pipeline,
+            sample_count,
+            accum,
+            revealage,
+            bind_group,
+        }
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> (Target, Target, wgpu::BindGroup) {
+        let texture_view = |label, format, sample_count, usage| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width: width.max(1),
+                        height: height.max(1),
+                        depth_or_array_layers: 1,
+                    },
+                    dimension: wgpu::TextureDimension::D2,
+                    mip_level_count: 1,
+                    sample_count,
+                    format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT | usage,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let target = |label, format| Target {
+            view: texture_view(label, format, 1, wgpu::TextureUsages::TEXTURE_BINDING),
+            multisampled: (sample_count > 1)
+                .then(|| texture_view(label, format, sample_count, wgpu::TextureUsages::empty())),
+        };
+        let accum = target("oit accum", Self::ACCUM_FORMAT);
+        let revealage = target("oit revealage", Self::REVEALAGE_FORMAT);
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("oit composite"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&accum.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&revealage.view),
+                },
+            ],
+        });
+        (accum, revealage, bind_group)
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.accum, self.revealage, self.bind_group) =
+            Self::create_targets(device, &self.layout, width, height, self.sample_count);
+    }
+
+    /// Begins the pass drawing the transparent geometry, tested against the
+    /// depth of the opaque scene.
+    pub fn accumulate<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        depth_view: &'a wgpu::TextureView,
+    ) -> wgpu::RenderPass<'a> {
+        // Cleared in a pass of its own, the GL backend only clears the first
+        // of several color attachments correctly
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("oit clear revealage"),
+            color_attachments: &[self
+                .revealage
+                .attachment(wgpu::LoadOp::Clear(wgpu::Color::WHITE))],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("oit accumulation"),
+            color_attachments: &[
+                self.accum
+                    .attachment(wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT)),
+                self.revealage.attachment(wgpu::LoadOp::Load),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        })
+    }
+
+    /// Blends the accumulated fragments over `target`.
+    pub fn composite(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("oit composite"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.composite_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
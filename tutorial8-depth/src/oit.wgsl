@@ -0,0 +1,19 @@
+// Composites the accumulated transparent fragments over the opaque scene;
+// appended to fullscreen.wgsl, whose input texture holds the accumulation.
+
+@group(0) @binding(2)
+var revealage_texture: texture_2d<f32>;
+
+@fragment
+fn fs_composite(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let coord = vec2<i32>(in.clip_position.xy);
+    let revealage = textureLoad(revealage_texture, coord, 0).r;
+    // Nothing transparent was drawn here
+    if revealage >= 1.0 {
+        discard;
+    }
+
+    let accum = textureLoad(input_texture, coord, 0);
+    let average = accum.rgb / clamp(accum.a, 1e-4, 5e4);
+    return vec4<f32>(average, 1.0 - revealage);
+}
@@ -0,0 +1,224 @@
+use std::path::PathBuf;
+
+use crate::adapter::{self, AdapterSelector};
+use crate::pacing;
+use crate::recording::RecordingOptions;
+
+/// Options given on the command line.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Overrides `WGPU_BACKEND`
+    pub backends: Option<wgpu::Backends>,
+    /// Overrides `WGPU_POWER_PREF`
+    pub power_preference: Option<wgpu::PowerPreference>,
+    /// Overrides `WGPU_ADAPTER_NAME`, and the power preference
+    pub adapter: Option<AdapterSelector>,
+    /// Request the software adapter
+    pub force_fallback_adapter: bool,
+    /// Print the adapters instead of opening the scene
+    pub list_adapters: bool,
+    pub present_mode: Option<wgpu::PresentMode>,
+    pub frame_latency: Option<u32>,
+    /// Frame rate cap
+    pub max_fps: Option<u32>,
+    /// Samples per pixel of the scene
+    pub msaa: Option<u32>,
+    /// Directory with six face images or a single cross layout image
+    pub skybox: Option<PathBuf>,
+    /// Radiance HDR or OpenEXR equirectangular panorama, replaces the skybox
+    pub environment: Option<PathBuf>,
+    /// Images drawn on the instances besides the happy tree
+    pub textures: Vec<PathBuf>,
+    /// Render a frame sequence offscreen instead of opening the scene
+    pub recording: Option<RecordingOptions>,
+}
+
+impl Options {
+    pub const USAGE: &'static str = "\
+Usage: tutorial8 [OPTIONS]
+
+Options:
+  --backend <LIST>       Comma-separated vulkan, metal, dx12, gl, primary or all [env: WGPU_BACKEND]
+  --power <PREFERENCE>   low, high or none [env: WGPU_POWER_PREF]
+  --adapter <ADAPTER>    Index from --list-adapters or part of the name [env: WGPU_ADAPTER_NAME]
+  --fallback-adapter     Use the software adapter
+  --list-adapters        Print the adapters with their features and limits and exit
+  --present-mode <MODE>  fifo, fifo-relaxed, mailbox or immediate [default: first supported]
+  --frame-latency <N>    Desired maximum frame latency of the surface [default: 2]
+  --max-fps <FPS>        Cap the frame rate
+  --msaa <SAMPLES>       Multisample the scene with 1 or 4 samples [default: 1]
+  --skybox <PATH>        Directory with px, nx, py, ny, pz and nz images, or a cross layout image
+  --environment <FILE>   Equirectangular .hdr or .exr panorama for the sky and the lighting
+  --texture <FILE>       Add an image to vary the instances with, may be repeated
+  --record <FRAMES>      Render FRAMES frames offscreen with a fixed timestep and exit
+  --fps <FPS>            Frame rate of the recording [default: 60]
+  --size <W>x<H>         Resolution of the recording [default: 1280x720]
+  --out <DIR>            Directory for the PNG sequence [default: frames]
+  --y4m <FILE>           Also write the recording as a raw YUV4MPEG2 stream
+  -h, --help             Print this help";
+
+    /// Parses the arguments following the program name.
+    ///
+    /// Returns `Ok(None)` if the help was requested.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut options = Options::default();
+        let mut recording = RecordingOptions::default();
+        let mut record = false;
+        // Last option that only applies to a recording
+        let mut recording_only = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if matches!(arg.as_str(), "--fps" | "--size" | "--out" | "--y4m") {
+                recording_only = Some(arg.clone());
+            }
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for '{}'", arg))
+            };
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "--backend" => options.backends = Some(adapter::parse_backends(&value()?)?),
+                "--power" => {
+                    options.power_preference = Some(adapter::parse_power_preference(&value()?)?)
+                }
+                "--adapter" => options.adapter = Some(AdapterSelector::parse(&value()?)),
+                "--fallback-adapter" => options.force_fallback_adapter = true,
+                "--list-adapters" => options.list_adapters = true,
+                "--present-mode" => {
+                    options.present_mode = Some(pacing::parse_present_mode(&value()?)?)
+                }
+                "--frame-latency" => options.frame_latency = Some(parse_number(&value()?)?),
+                "--max-fps" => options.max_fps = Some(parse_number(&value()?)?),
+                "--msaa" => {
+                    options.msaa = match parse_number(&value()?)? {
+                        samples @ (1 | 4) => Some(samples),
+                        samples => return Err(format!("unsupported sample count {}", samples)),
+                    }
+                }
+                "--skybox" => options.skybox = Some(value()?.into()),
+                "--environment" => options.environment = Some(value()?.into()),
+                "--texture" => options.textures.push(value()?.into()),
+                "--record" => {
+                    recording.frames = parse_number(&value()?)?;
+                    record = true;
+                }
+                "--fps" => recording.fps = parse_number(&value()?)?,
+                "--size" => {
+                    let value = value()?;
+                    let (width, height) = value
+                        .split_once('x')
+                        .ok_or_else(|| format!("invalid size '{}'", value))?;
+                    recording.width = parse_number(width)?;
+                    recording.height = parse_number(height)?;
+                }
+                "--out" => recording.output_dir = value()?.into(),
+                "--y4m" => recording.y4m = Some(value()?.into()),
+                _ => return Err(format!("unexpected argument '{}'", arg)),
+            }
+        }
+
+        if record {
+            options.recording = Some(recording);
+        } else if let Some(arg) = recording_only {
+            return Err(format!("'{}' requires '--record'", arg));
+        }
+        Ok(Some(options))
+    }
+}
+
+fn parse_number(value: &str) -> Result<u32, String> {
+    match value.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("expected a positive number, got '{}'", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Options>, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_the_defaults_and_help() {
+        let options = parse(&[]).unwrap().unwrap();
+        assert!(options.backends.is_none());
+        assert!(options.recording.is_none());
+        assert!(parse(&["--msaa", "4", "--help"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn parses_the_options() {
+        let options = parse(&[
+            "--backend",
+            "vulkan,gl",
+            "--present-mode",
+            "mailbox",
+            "--msaa",
+            "4",
+            "--texture",
+            "a.png",
+            "--texture",
+            "b.png",
+            "--fallback-adapter",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            options.backends,
+            Some(wgpu::Backends::VULKAN | wgpu::Backends::GL)
+        );
+        assert_eq!(options.present_mode, Some(wgpu::PresentMode::Mailbox));
+        assert_eq!(options.msaa, Some(4));
+        assert_eq!(
+            options.textures,
+            [PathBuf::from("a.png"), PathBuf::from("b.png")]
+        );
+        assert!(options.force_fallback_adapter);
+    }
+
+    #[test]
+    fn parses_the_recording() {
+        let options = parse(&[
+            "--size", "320x240", "--record", "10", "--fps", "30", "--out", "out", "--y4m",
+            "out.y4m",
+        ])
+        .unwrap()
+        .unwrap();
+        let recording = options.recording.unwrap();
+        assert_eq!(
+            [
+                recording.frames,
+                recording.fps,
+                recording.width,
+                recording.height
+            ],
+            [10, 30, 320, 240]
+        );
+        assert_eq!(recording.output_dir, PathBuf::from("out"));
+        assert_eq!(recording.y4m, Some(PathBuf::from("out.y4m")));
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        let error = |args: &[&str]| parse(args).unwrap_err();
+        assert_eq!(error(&["--fps", "30"]), "'--fps' requires '--record'");
+        assert_eq!(error(&["--record"]), "missing value for '--record'");
+        assert_eq!(
+            error(&["--record", "0"]),
+            "expected a positive number, got '0'"
+        );
+        assert_eq!(
+            error(&["--record", "1", "--size", "320"]),
+            "invalid size '320'"
+        );
+        assert_eq!(error(&["--msaa", "2"]), "unsupported sample count 2");
+        assert_eq!(
+            error(&["--frobnicate"]),
+            "unexpected argument '--frobnicate'"
+        );
+    }
+}
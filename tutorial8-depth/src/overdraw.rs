@@ -0,0 +1,270 @@
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct OverdrawUniform {
+    max_count: f32,
+    _padding: [u32; 3],
+}
+
+/// Debug view counting the fragments drawn into each pixel.
+///
+/// The instances are drawn again into a counter target, adding one per
+/// fragment that passes the depth test, and the counts are mapped to a color
+/// ramp over the final image.
+pub struct Overdraw {
+    layout: wgpu::BindGroupLayout,
+    heat_map_pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    sample_count: u32,
+    targets: Targets,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Rendered to at the sample count of the scene; the counts are averaged
+/// over the samples when resolved.
+struct Targets {
+    counter: wgpu::TextureView,
+    multisampled: Option<wgpu::TextureView>,
+}
+
+impl Overdraw {
+    pub const COUNTER_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R16Float;
+
+    /// Color target of the pipelines drawing the instances into the counter.
+    pub fn counter_target() -> [Option<wgpu::ColorTargetState>; 1] {
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        [Some(wgpu::ColorTargetState {
+            format: Self::COUNTER_FORMAT,
+            blend: Some(wgpu::BlendState {
+                color: additive,
+                alpha: additive,
+            }),
+            write_mask: wgpu::ColorWrites::ALL,
+        })]
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> Self {
+        // Binding 1 is the sampler of fullscreen.wgsl, unused here
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("overdraw heat map"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("overdraw heat map"),
+            source: wgpu::ShaderSource::Wgsl(
+                format!(
+                    "{}\n{}",
+                    include_str!("fullscreen.wgsl"),
+                    include_str!("overdraw.wgsl")
+                )
+                .into(),
+            ),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("overdraw heat map"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let heat_map_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("overdraw heat map"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader_module,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader_module,
+                entry_point: "fs_heat_map",
+                targets: &[Some(color_format.into())],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("overdraw uniforms"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            size: std::mem::size_of::<OverdrawUniform>() as u64,
+            mapped_at_creation: false,
+        });
+
+        let targets = Targets::new(device, width, height, sample_count);
+        let bind_group = Self::create_bind_group(device, &layout, &targets, &uniform_buffer);
+
+        Self {
+            layout,
+            heat_map_pipeline,
+            uniform_buffer,
+            sample_count,
+            targets,
+            bind_group,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        targets: &Targets,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("overdraw heat map"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&targets.counter),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.targets = Targets::new(device, width, height, self.sample_count);
+        self.bind_group =
+            Self::create_bind_group(device, &self.layout, &self.targets, &self.uniform_buffer);
+    }
+
+    /// `max_count` fragments map to the top of the color ramp.
+    pub fn prepare(&self, queue: &wgpu::Queue, max_count: f32) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&OverdrawUniform {
+                max_count,
+                _padding: [0; 3],
+            }),
+        );
+    }
+
+    /// Begins the pass counting the fragments of the instances, with a depth
+    /// buffer apart from the scene's that is kept if it was filled by a
+    /// `depth_prepass`.
+    pub fn count<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        depth_view: &'a wgpu::TextureView,
+        depth_prepass: bool,
+    ) -> wgpu::RenderPass<'a> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("overdraw count"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: self
+                    .targets
+                    .multisampled
+                    .as_ref()
+                    .unwrap_or(&self.targets.counter),
+                resolve_target: self
+                    .targets
+                    .multisampled
+                    .as_ref()
+                    .map(|_| &self.targets.counter),
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: if depth_prepass {
+                        wgpu::LoadOp::Load
+                    } else {
+                        wgpu::LoadOp::Clear(1.0)
+                    },
+                    store: wgpu::StoreOp::Discard,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        })
+    }
+
+    /// Draws the counts as a heat map over `target`.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("overdraw heat map"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.heat_map_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+impl Targets {
+    fn new(device: &wgpu::Device, width: u32, height: u32, sample_count: u32) -> Self {
+        let counter = |sample_count, usage| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some("overdraw counter"),
+                    size: wgpu::Extent3d {
+                        width: width.max(1),
+                        height: height.max(1),
+                        depth_or_array_layers: 1,
+                    },
+                    dimension: wgpu::TextureDimension::D2,
+                    mip_level_count: 1,
+                    sample_count,
+                    format: Overdraw::COUNTER_FORMAT,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT | usage,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        Self {
+            counter: counter(1, wgpu::TextureUsages::TEXTURE_BINDING),
+            multisampled: (sample_count > 1)
+                .then(|| counter(sample_count, wgpu::TextureUsages::empty())),
+        }
+    }
+}
@@ -0,0 +1,31 @@
+// Maps the fragment count per pixel to a heat color; appended to
+// fullscreen.wgsl, whose input texture holds the counts.
+
+struct OverdrawUniform {
+    // Count at the top of the color ramp
+    max_count: f32,
+}
+
+@group(0) @binding(2)
+var<uniform> overdraw: OverdrawUniform;
+
+// Black to blue to green to yellow to red to white
+fn heat_ramp(value: f32) -> vec3<f32> {
+    var colors = array<vec3<f32>, 6>(
+        vec3<f32>(0.0, 0.0, 0.0),
+        vec3<f32>(0.1, 0.2, 0.9),
+        vec3<f32>(0.1, 0.8, 0.3),
+        vec3<f32>(1.0, 0.9, 0.1),
+        vec3<f32>(0.9, 0.1, 0.1),
+        vec3<f32>(1.0, 1.0, 1.0),
+    );
+    let x = saturate(value) * 5.0;
+    let i = min(u32(x), 4u);
+    return mix(colors[i], colors[i + 1u], x - f32(i));
+}
+
+@fragment
+fn fs_heat_map(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let count = textureLoad(input_texture, vec2<i32>(in.clip_position.xy), 0).r;
+    return vec4<f32>(heat_ramp(count / overdraw.max_count), 1.0);
+}
@@ -0,0 +1,182 @@
+use std::time::Duration;
+
+use instant::Instant;
+
+/// Present modes that can be selected, in the order they are cycled through.
+pub const PRESENT_MODES: [wgpu::PresentMode; 4] = [
+    wgpu::PresentMode::Fifo,
+    wgpu::PresentMode::FifoRelaxed,
+    wgpu::PresentMode::Mailbox,
+    wgpu::PresentMode::Immediate,
+];
+
+pub fn parse_present_mode(value: &str) -> Result<wgpu::PresentMode, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "fifo" => Ok(wgpu::PresentMode::Fifo),
+        "fifo-relaxed" | "fiforelaxed" => Ok(wgpu::PresentMode::FifoRelaxed),
+        "mailbox" => Ok(wgpu::PresentMode::Mailbox),
+        "immediate" => Ok(wgpu::PresentMode::Immediate),
+        _ => Err(format!("unknown present mode '{}'", value)),
+    }
+}
+
+/// Picks `requested` if the surface supports it, and the first supported mode otherwise.
+pub fn select_present_mode(
+    requested: Option<wgpu::PresentMode>,
+    supported: &[wgpu::PresentMode],
+) -> wgpu::PresentMode {
+    match requested {
+        Some(mode) if supported.contains(&mode) => mode,
+        Some(mode) => {
+            log::warn!(
+                "present mode {:?} is not supported (supported: {:?})",
+                mode,
+                supported
+            );
+            supported[0]
+        }
+        None => supported[0],
+    }
+}
+
+/// Returns the supported present mode following `current`.
+pub fn next_present_mode(
+    current: wgpu::PresentMode,
+    supported: &[wgpu::PresentMode],
+) -> wgpu::PresentMode {
+    let index = PRESENT_MODES
+        .iter()
+        .position(|&m| m == current)
+        .unwrap_or(0);
+    (1..=PRESENT_MODES.len())
+        .map(|i| PRESENT_MODES[(index + i) % PRESENT_MODES.len()])
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(current)
+}
+
+/// Caps the frame rate by delaying redraw requests.
+pub struct FrameLimiter {
+    pub max_fps: Option<u32>,
+    next_frame: Instant,
+}
+
+impl FrameLimiter {
+    /// Frame rate caps cycled through at runtime
+    pub const CAPS: [Option<u32>; 4] = [None, Some(30), Some(60), Some(144)];
+
+    pub fn new(max_fps: Option<u32>) -> Self {
+        Self {
+            max_fps,
+            next_frame: Instant::now(),
+        }
+    }
+
+    pub fn cycle(&mut self) {
+        let index = Self::CAPS.iter().position(|&c| c == self.max_fps);
+        self.max_fps = Self::CAPS[index.map_or(0, |i| (i + 1) % Self::CAPS.len())];
+    }
+
+    /// Called after a frame was presented. Returns how long to wait before
+    /// the next frame, or `None` if it can be rendered right away.
+    pub fn frame_presented(&mut self) -> Option<Duration> {
+        self.frame_presented_at(Instant::now())
+    }
+
+    fn frame_presented_at(&mut self, now: Instant) -> Option<Duration> {
+        let fps = self.max_fps?;
+        // Frames are scheduled on a fixed grid unless we fell behind
+        self.next_frame += Duration::from_secs_f64(1.0 / fps as f64);
+        if self.next_frame < now {
+            self.next_frame = now;
+            return None;
+        }
+        Some(self.next_frame - now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wgpu::PresentMode::{Fifo, FifoRelaxed, Immediate, Mailbox};
+
+    #[test]
+    fn parses_present_modes() {
+        assert_eq!(parse_present_mode("Mailbox"), Ok(Mailbox));
+        assert_eq!(parse_present_mode("fifo-relaxed"), Ok(FifoRelaxed));
+        assert_eq!(
+            parse_present_mode("vsync"),
+            Err("unknown present mode 'vsync'".to_string())
+        );
+    }
+
+    #[test]
+    fn selects_the_requested_mode_if_supported() {
+        assert_eq!(
+            select_present_mode(Some(Mailbox), &[Fifo, Mailbox]),
+            Mailbox
+        );
+        assert_eq!(select_present_mode(Some(Immediate), &[Fifo, Mailbox]), Fifo);
+        assert_eq!(select_present_mode(None, &[Mailbox, Fifo]), Mailbox);
+    }
+
+    #[test]
+    fn cycles_through_the_supported_modes() {
+        let all = PRESENT_MODES;
+        assert_eq!(next_present_mode(Fifo, &all), FifoRelaxed);
+        assert_eq!(next_present_mode(Immediate, &all), Fifo);
+
+        // Unsupported modes are skipped
+        let supported = [Fifo, Immediate];
+        assert_eq!(next_present_mode(Fifo, &supported), Immediate);
+        assert_eq!(next_present_mode(Immediate, &supported), Fifo);
+        assert_eq!(next_present_mode(Fifo, &[Fifo]), Fifo);
+        // Or the current mode is kept if nothing is supported
+        assert_eq!(next_present_mode(Mailbox, &[]), Mailbox);
+    }
+
+    #[test]
+    fn cycles_the_frame_rate_caps() {
+        let mut limiter = FrameLimiter::new(None);
+        let mut caps = Vec::new();
+        for _ in 0..FrameLimiter::CAPS.len() {
+            limiter.cycle();
+            caps.push(limiter.max_fps);
+        }
+        assert_eq!(caps, [Some(30), Some(60), Some(144), None]);
+
+        // A cap from the command line restarts the cycle
+        let mut limiter = FrameLimiter::new(Some(75));
+        limiter.cycle();
+        assert_eq!(limiter.max_fps, None);
+    }
+
+    #[test]
+    fn waits_on_a_fixed_grid() {
+        let mut limiter = FrameLimiter::new(None);
+        assert_eq!(limiter.frame_presented_at(Instant::now()), None);
+
+        let mut limiter = FrameLimiter::new(Some(50));
+        let start = limiter.next_frame;
+        let frame = Duration::from_millis(20);
+        assert_eq!(limiter.frame_presented_at(start), Some(frame));
+        // The wait shrinks with a slower frame, without moving the grid
+        assert_eq!(
+            limiter.frame_presented_at(start + frame + Duration::from_millis(5)),
+            Some(Duration::from_millis(15))
+        );
+        assert_eq!(limiter.next_frame, start + frame * 2);
+    }
+
+    #[test]
+    fn restarts_the_grid_when_behind() {
+        let mut limiter = FrameLimiter::new(Some(50));
+        let late = limiter.next_frame + Duration::from_millis(100);
+        assert_eq!(limiter.frame_presented_at(late), None);
+        assert_eq!(limiter.next_frame, late);
+        assert_eq!(
+            limiter.frame_presented_at(late),
+            Some(Duration::from_millis(20))
+        );
+    }
+}
@@ -0,0 +1,485 @@
+use std::sync::{Arc, Mutex};
+
+use glam::{Mat4, Vec2, Vec3};
+
+use crate::{Instance, InstanceRaw, MyTexture};
+
+pub struct Ray {
+    pub origin: Vec3,
+    /// Normalized
+    pub direction: Vec3,
+}
+
+impl Ray {
+    /// The ray through a window position in physical pixels.
+    pub fn from_cursor(cursor: Vec2, size: Vec2, inv_view_proj: Mat4) -> Self {
+        let ndc = Vec2::new(cursor.x / size.x * 2.0 - 1.0, 1.0 - cursor.y / size.y * 2.0);
+        let near = inv_view_proj.project_point3(ndc.extend(0.0));
+        let far = inv_view_proj.project_point3(ndc.extend(1.0));
+        Self {
+            origin: near,
+            direction: (far - near).normalize(),
+        }
+    }
+
+    /// Distance along the ray to the first intersection with a sphere.
+    fn intersect_sphere(&self, center: Vec3, radius: f32) -> Option<f32> {
+        let offset = self.origin - center;
+        let b = offset.dot(self.direction);
+        let c = offset.length_squared() - radius * radius;
+        let discriminant = b * b - c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let t = -b - discriminant.sqrt();
+        // Starting inside the sphere counts as a hit at the origin
+        if t >= 0.0 {
+            Some(t)
+        } else if c <= 0.0 {
+            Some(0.0)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct Hit {
+    /// Index into the instances
+    pub index: usize,
+    /// Distance along the ray
+    pub distance: f32,
+}
+
+/// Spatial grid over the `x`/`z` positions of the instances.
+///
+/// The instances keep their place on the integer lattice while animating, so
+/// the grid is only rebuilt with the instances; the heights and scales are
+/// taken from the current [`InstanceRaw`]s when casting.
+pub struct InstanceGrid {
+    /// The lattice spans `-n..=n` on both axes
+    n: i32,
+    /// Instance index per lattice point, row by row along `z`
+    cells: Vec<u32>,
+}
+
+impl InstanceGrid {
+    const EMPTY: u32 = u32::MAX;
+
+    pub fn new(instances: &[Instance]) -> Self {
+        let n = instances
+            .iter()
+            .map(|inst| inst.x.abs().max(inst.z.abs()))
+            .max()
+            .unwrap_or(0);
+        let side = (2 * n + 1) as usize;
+        let mut cells = vec![Self::EMPTY; side * side];
+        for (i, inst) in instances.iter().enumerate() {
+            cells[(inst.z + n) as usize * side + (inst.x + n) as usize] = i as u32;
+        }
+        Self { n, cells }
+    }
+
+    fn get(&self, x: i32, z: i32) -> Option<usize> {
+        if x.abs() > self.n || z.abs() > self.n {
+            return None;
+        }
+        let side = 2 * self.n + 1;
+        let index = self.cells[((z + self.n) * side + x + self.n) as usize];
+        (index != Self::EMPTY).then_some(index as usize)
+    }
+
+    /// Finds the nearest instance whose bounding sphere the ray hits, walking
+    /// the cells under the ray from front to back.
+    pub fn cast(&self, ray: &Ray, raws: &[InstanceRaw]) -> Option<Hit> {
+        if self.cells.iter().all(|&index| index == Self::EMPTY) {
+            return None;
+        }
+
+        // Quads reach at most half their scale from the instance origin
+        let radius = raws.iter().map(|raw| raw.scale).fold(0.0, f32::max) * 0.5;
+        let (min_y, max_y) = raws
+            .iter()
+            .map(|raw| raw.matrix[13])
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), y| {
+                (min.min(y), max.max(y))
+            });
+        // Instances overlapping into neighbouring cells
+        let reach = (radius - 0.5).max(0.0).ceil() as i32;
+
+        // Clip the ray to the bounds of the whole grid
+        let extent = self.n as f32 + 0.5 + radius;
+        let bounds_min = Vec3::new(-extent, min_y - radius, -extent);
+        let bounds_max = Vec3::new(extent, max_y + radius, extent);
+        let inv_direction = ray.direction.recip();
+        let t0 = (bounds_min - ray.origin) * inv_direction;
+        let t1 = (bounds_max - ray.origin) * inv_direction;
+        let t_enter = t0.min(t1).max_element().max(0.0);
+        let t_exit = t0.max(t1).min_element();
+        if t_enter > t_exit {
+            return None;
+        }
+
+        // Cells are centered on the lattice points, step through them with a
+        // 2D DDA on the xz plane
+        let start = ray.origin + ray.direction * t_enter;
+        let mut cell = [
+            (start.x + 0.5).floor() as i32,
+            (start.z + 0.5).floor() as i32,
+        ];
+        let direction = [ray.direction.x, ray.direction.z];
+        let origin = [ray.origin.x, ray.origin.z];
+        let mut step = [0; 2];
+        let mut t_max = [f32::INFINITY; 2];
+        let mut t_delta = [f32::INFINITY; 2];
+        for axis in 0..2 {
+            if direction[axis] != 0.0 {
+                step[axis] = direction[axis].signum() as i32;
+                let boundary = cell[axis] as f32 + 0.5 * step[axis] as f32;
+                t_max[axis] = (boundary - origin[axis]) / direction[axis];
+                t_delta[axis] = 1.0 / direction[axis].abs();
+            }
+        }
+
+        let mut nearest: Option<Hit> = None;
+        loop {
+            for dz in -reach..=reach {
+                for dx in -reach..=reach {
+                    let Some(index) = self.get(cell[0] + dx, cell[1] + dz) else {
+                        continue;
+                    };
+                    let raw = &raws[index];
+                    let center = Vec3::from_slice(&raw.matrix[12..15]);
+                    if let Some(distance) = ray.intersect_sphere(center, 0.5 * raw.scale) {
+                        if nearest.as_ref().is_none_or(|hit| distance < hit.distance) {
+                            nearest = Some(Hit { index, distance });
+                        }
+                    }
+                }
+            }
+
+            // Nothing in the cells further along can be nearer
+            let cell_exit = t_max[0].min(t_max[1]);
+            if nearest
+                .as_ref()
+                .is_some_and(|hit| hit.distance <= cell_exit)
+                || cell_exit > t_exit
+            {
+                return nearest;
+            }
+
+            let axis = if t_max[0] < t_max[1] { 0 } else { 1 };
+            cell[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+        }
+    }
+}
+
+/// Result of [`IdPicking`].
+pub struct IdHit {
+    /// Index into the instances
+    pub index: usize,
+    /// Value of the depth buffer under the cursor
+    pub depth: f32,
+    /// Reconstructed from the depth
+    pub position: Vec3,
+}
+
+struct IdTargets {
+    /// Instance index plus one, zero where nothing is drawn
+    id: wgpu::Texture,
+    id_view: wgpu::TextureView,
+    /// The depth of the front-most fragment, copyable unlike a depth buffer
+    depth: wgpu::Texture,
+    depth_view: wgpu::TextureView,
+    /// Single-sampled, unlike the depth buffer of the scene
+    depth_buffer: wgpu::TextureView,
+}
+
+impl IdTargets {
+    fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let texture = |label, format| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                dimension: wgpu::TextureDimension::D2,
+                mip_level_count: 1,
+                sample_count: 1,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            })
+        };
+        let id = texture("id picking ids", IdPicking::ID_FORMAT);
+        let depth = texture("id picking depth", IdPicking::DEPTH_FORMAT);
+        Self {
+            id_view: id.create_view(&wgpu::TextureViewDescriptor::default()),
+            id,
+            depth_view: depth.create_view(&wgpu::TextureViewDescriptor::default()),
+            depth,
+            depth_buffer: MyTexture::create_depth_texture(device, width, height, 1).view,
+        }
+    }
+}
+
+/// A pick waiting to be read back.
+struct PendingPick {
+    pixel: [u32; 2],
+    size: [u32; 2],
+    inv_view_proj: Mat4,
+    /// Instance index per position in the instance buffer, empty if unsorted
+    order: Vec<u32>,
+}
+
+/// Picks by rendering the instance indices into an ID buffer and reading back
+/// the pixel under the cursor.
+///
+/// Unlike [`InstanceGrid::cast`], the result matches whatever the vertex
+/// shader does to the geometry. The readback is asynchronous, so the result
+/// arrives a frame or more after [`IdPicking::request`].
+pub struct IdPicking {
+    pipeline: wgpu::RenderPipeline,
+    targets: IdTargets,
+    readback_buffer: wgpu::Buffer,
+    /// Pixel under the cursor
+    requested: Option<[u32; 2]>,
+    in_flight: Option<PendingPick>,
+    mapped: Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>,
+}
+
+impl IdPicking {
+    const ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+    const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+
+    /// `layout`, `shader_module` and `primitive` are those of the scene
+    /// pipelines, the instances are drawn with `vs_main` and `fs_id`.
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader_module: &wgpu::ShaderModule,
+        buffers: &[wgpu::VertexBufferLayout],
+        primitive: wgpu::PrimitiveState,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let pipeline = Self::create_pipeline(device, layout, shader_module, buffers, primitive);
+
+        // The ID followed by the depth
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("id picking readback"),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            size: 8,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            pipeline,
+            targets: IdTargets::new(device, width, height),
+            readback_buffer,
+            requested: None,
+            in_flight: None,
+            mapped: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader_module: &wgpu::ShaderModule,
+        buffers: &[wgpu::VertexBufferLayout],
+        primitive: wgpu::PrimitiveState,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("id picking"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader_module,
+                entry_point: "vs_main",
+                buffers,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader_module,
+                entry_point: "fs_id",
+                targets: &[
+                    Some(Self::ID_FORMAT.into()),
+                    Some(Self::DEPTH_FORMAT.into()),
+                ],
+            }),
+            primitive,
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: MyTexture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    /// Recreates the pipeline after the scene pipelines changed their
+    /// primitive state, so the picked faces stay the drawn ones.
+    pub fn set_primitive(
+        &mut self,
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader_module: &wgpu::ShaderModule,
+        buffers: &[wgpu::VertexBufferLayout],
+        primitive: wgpu::PrimitiveState,
+    ) {
+        self.pipeline = Self::create_pipeline(device, layout, shader_module, buffers, primitive);
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.targets = IdTargets::new(device, width, height);
+        // The cursor position may be outside the new size
+        self.requested = None;
+    }
+
+    /// Picks at `cursor` (in physical pixels) with the next rendered frame.
+    pub fn request(&mut self, cursor: Vec2) {
+        self.requested = Some([
+            (cursor.x.max(0.0) as u32).min(self.targets.id.width() - 1),
+            (cursor.y.max(0.0) as u32).min(self.targets.id.height() - 1),
+        ]);
+    }
+
+    /// Whether a requested pick is waiting to be rendered; the readback
+    /// buffer has to be free for it.
+    pub fn is_requested(&self) -> bool {
+        self.requested.is_some() && self.in_flight.is_none()
+    }
+
+    /// Begins the pass drawing the instances into the ID buffer, limited to
+    /// the pixel under the cursor.
+    ///
+    /// Must only be called while [`IdPicking::is_requested`].
+    pub fn begin<'a>(&'a self, encoder: &'a mut wgpu::CommandEncoder) -> wgpu::RenderPass<'a> {
+        let [x, y] = self.requested.unwrap();
+        let attachment = |view| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Where the GL backend misdirects the clear of the depth
+                    // target, both clear to zero and only drawn pixels are read
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("id picking"),
+            color_attachments: &[
+                attachment(&self.targets.id_view),
+                attachment(&self.targets.depth_view),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.targets.depth_buffer,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Discard,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_scissor_rect(x, y, 1, 1);
+        render_pass
+    }
+
+    /// Copies the pixel under the cursor into the readback buffer, after the
+    /// pass from [`IdPicking::begin`].
+    pub fn copy(&self, encoder: &mut wgpu::CommandEncoder) {
+        let [x, y] = self.requested.unwrap();
+        for (offset, texture) in [(0, &self.targets.id), (4, &self.targets.depth)] {
+            encoder.copy_texture_to_buffer(
+                wgpu::ImageCopyTexture {
+                    texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x, y, z: 0 },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::ImageCopyBuffer {
+                    buffer: &self.readback_buffer,
+                    layout: wgpu::ImageDataLayout {
+                        offset,
+                        ..Default::default()
+                    },
+                },
+                wgpu::Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+    }
+
+    /// Starts reading back the pixel; must be called after the command
+    /// buffer from [`IdPicking::copy`] is submitted.
+    ///
+    /// `view_proj` is the one the frame was drawn with and `order` maps the
+    /// positions in its instance buffer to the instance indices, if the
+    /// instances were sorted.
+    pub fn map(&mut self, view_proj: Mat4, order: Vec<u32>) {
+        let mapped = self.mapped.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                *mapped.lock().unwrap() = Some(result);
+            });
+        self.in_flight = self.requested.take().map(|pixel| PendingPick {
+            pixel,
+            size: [self.targets.id.width(), self.targets.id.height()],
+            inv_view_proj: view_proj.inverse(),
+            order,
+        });
+    }
+
+    /// Returns the result of the pick once the readback has completed, `None`
+    /// inside if no instance is under the cursor.
+    ///
+    /// A failed `map` is logged and drops the pick, so the next request can
+    /// use the readback buffer again.
+    pub fn try_read(&mut self) -> Option<Option<IdHit>> {
+        self.in_flight.as_ref()?;
+        if let Err(err) = self.mapped.lock().unwrap().take()? {
+            log::error!("pick readback failed: {}", err);
+            self.readback_buffer.unmap();
+            self.in_flight = None;
+            return None;
+        }
+        let (id, depth) = {
+            let view = self.readback_buffer.slice(..).get_mapped_range();
+            let [id, depth]: [u32; 2] = bytemuck::pod_read_unaligned(&view);
+            (id, f32::from_bits(depth))
+        };
+        self.readback_buffer.unmap();
+        let pick = self.in_flight.take().unwrap();
+
+        let Some(position) = id.checked_sub(1) else {
+            return Some(None);
+        };
+        let index = match pick.order.get(position as usize) {
+            Some(&index) => index,
+            None => position,
+        };
+        // Center of the pixel in normalized device coordinates
+        let ndc = Vec2::new(
+            (pick.pixel[0] as f32 + 0.5) / pick.size[0] as f32 * 2.0 - 1.0,
+            1.0 - (pick.pixel[1] as f32 + 0.5) / pick.size[1] as f32 * 2.0,
+        );
+        Some(Some(IdHit {
+            index: index as usize,
+            depth,
+            position: pick.inv_view_proj.project_point3(ndc.extend(depth)),
+        }))
+    }
+}
@@ -0,0 +1,610 @@
+use crate::settings::{ColorGrading, PostProcessing, Tonemapping};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemapParams {
+    exposure: f32,
+    mode: u32,
+    _padding: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomParams {
+    threshold: f32,
+    intensity: f32,
+    direction: [f32; 2],
+}
+
+/// Color texture the effects render to and read from.
+struct RenderTarget {
+    view: wgpu::TextureView,
+    /// Binds the texture as the input of the next pass
+    bind_group: wgpu::BindGroup,
+}
+
+impl RenderTarget {
+    fn new(
+        device: &wgpu::Device,
+        label: &str,
+        input_layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let view = device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: width.max(1),
+                    height: height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                dimension: wgpu::TextureDimension::D2,
+                mip_level_count: 1,
+                sample_count: 1,
+                format: PostProcess::HDR_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(label),
+            layout: input_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        });
+
+        Self { view, bind_group }
+    }
+}
+
+/// A full-screen pass reading the previous target.
+///
+/// The fragment shader gets the input texture and sampler in group 0 and a
+/// small uniform buffer at binding 0 of group 1, followed by any extra
+/// resources of the effect.
+struct Effect {
+    label: &'static str,
+    /// Rendering to an intermediate target and to the output
+    pipelines: [wgpu::RenderPipeline; 2],
+    params_layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Effect {
+    const UNIFORM_SIZE: u64 = 16;
+
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        device: &wgpu::Device,
+        label: &'static str,
+        input_layout: &wgpu::BindGroupLayout,
+        output_format: wgpu::TextureFormat,
+        shader: &str,
+        entry_point: &str,
+        extra_bindings: &[wgpu::BindingType],
+        extra_resources: &[wgpu::BindingResource],
+    ) -> Self {
+        let mut entries = vec![wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }];
+        entries.extend(extra_bindings.iter().enumerate().map(|(i, &ty)| {
+            wgpu::BindGroupLayoutEntry {
+                binding: i as u32 + 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty,
+                count: None,
+            }
+        }));
+        let params_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(label),
+            entries: &entries,
+        });
+
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(
+                format!("{}\n{}", include_str!("fullscreen.wgsl"), shader).into(),
+            ),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(label),
+            bind_group_layouts: &[input_layout, &params_layout],
+            push_constant_ranges: &[],
+        });
+        let pipelines = [PostProcess::HDR_FORMAT, output_format].map(|format| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader_module,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader_module,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            size: Self::UNIFORM_SIZE,
+            mapped_at_creation: false,
+        });
+        let bind_group = Self::create_bind_group(
+            device,
+            label,
+            &params_layout,
+            &uniform_buffer,
+            extra_resources,
+        );
+
+        Self {
+            label,
+            pipelines,
+            params_layout,
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        label: &str,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        extra_resources: &[wgpu::BindingResource],
+    ) -> wgpu::BindGroup {
+        let mut entries = vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: uniform_buffer.as_entire_binding(),
+        }];
+        entries.extend(extra_resources.iter().enumerate().map(|(i, resource)| {
+            wgpu::BindGroupEntry {
+                binding: i as u32 + 1,
+                resource: resource.clone(),
+            }
+        }));
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(label),
+            layout,
+            entries: &entries,
+        })
+    }
+
+    /// Replaces the extra resources, e.g. after they were recreated.
+    fn rebind(&mut self, device: &wgpu::Device, extra_resources: &[wgpu::BindingResource]) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            self.label,
+            &self.params_layout,
+            &self.uniform_buffer,
+            extra_resources,
+        );
+    }
+
+    fn write_params<T: bytemuck::Pod>(&self, queue: &wgpu::Queue, params: &T) {
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(params));
+    }
+
+    fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::BindGroup,
+        target: &wgpu::TextureView,
+        to_output: bool,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(self.label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.pipelines[to_output as usize]);
+        render_pass.set_bind_group(0, input, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+/// Bright pass and blur at half resolution, added back onto the scene.
+struct Bloom {
+    extract: Effect,
+    blur_horizontal: Effect,
+    blur_vertical: Effect,
+    composite: Effect,
+    targets: [RenderTarget; 2],
+}
+
+/// Chain of full-screen effects between the HDR scene target and the output.
+///
+/// The scene is rendered to the first of two ping-pong targets. Each enabled
+/// effect reads one target and writes the other; the last one writes the
+/// output view. The stages are bloom, tonemapping, color grading, sharpening,
+/// FXAA and the vignette. Tonemapping is always applied.
+pub struct PostProcess {
+    input_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    targets: [RenderTarget; 2],
+    bloom: Bloom,
+    tonemap: Effect,
+    color_grading: Effect,
+    lut_texture: wgpu::Texture,
+    sharpen: Effect,
+    fxaa: Effect,
+    vignette: Effect,
+    settings: PostProcessing,
+}
+
+impl PostProcess {
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    /// Number of entries per axis of the color grading LUT
+    const LUT_SIZE: u32 = 32;
+
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        output_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let input_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("post-processing input"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("post-processing input"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let targets = Self::create_targets(
+            device,
+            "post-processing",
+            &input_layout,
+            &sampler,
+            width,
+            height,
+        );
+        let bloom_targets = Self::create_targets(
+            device,
+            "bloom",
+            &input_layout,
+            &sampler,
+            width / 2,
+            height / 2,
+        );
+
+        let effect = |label, shader: &str, entry_point: &str| {
+            Effect::new(
+                device,
+                label,
+                &input_layout,
+                output_format,
+                shader,
+                entry_point,
+                &[],
+                &[],
+            )
+        };
+
+        let texture_binding = |view_dimension| wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            view_dimension,
+            multisampled: false,
+        };
+        let bloom = Bloom {
+            extract: effect("bloom extract", include_str!("bloom.wgsl"), "fs_extract"),
+            blur_horizontal: effect(
+                "bloom blur horizontal",
+                include_str!("bloom.wgsl"),
+                "fs_blur",
+            ),
+            blur_vertical: effect("bloom blur vertical", include_str!("bloom.wgsl"), "fs_blur"),
+            composite: Effect::new(
+                device,
+                "bloom composite",
+                &input_layout,
+                output_format,
+                include_str!("bloom.wgsl"),
+                "fs_composite",
+                &[texture_binding(wgpu::TextureViewDimension::D2)],
+                &[wgpu::BindingResource::TextureView(&bloom_targets[0].view)],
+            ),
+            targets: bloom_targets,
+        };
+
+        let lut_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("color grading lut"),
+            size: wgpu::Extent3d {
+                width: Self::LUT_SIZE,
+                height: Self::LUT_SIZE,
+                depth_or_array_layers: Self::LUT_SIZE,
+            },
+            dimension: wgpu::TextureDimension::D3,
+            mip_level_count: 1,
+            sample_count: 1,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let color_grading = Effect::new(
+            device,
+            "color grading",
+            &input_layout,
+            output_format,
+            include_str!("color_grading.wgsl"),
+            "fs_main",
+            &[texture_binding(wgpu::TextureViewDimension::D3)],
+            &[wgpu::BindingResource::TextureView(
+                &lut_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            )],
+        );
+
+        let post_process = Self {
+            tonemap: effect("tonemap", include_str!("tonemap.wgsl"), "fs_main"),
+            sharpen: effect("sharpen", include_str!("sharpen.wgsl"), "fs_main"),
+            fxaa: effect("fxaa", include_str!("fxaa.wgsl"), "fs_main"),
+            vignette: effect("vignette", include_str!("vignette.wgsl"), "fs_main"),
+            input_layout,
+            sampler,
+            targets,
+            bloom,
+            color_grading,
+            lut_texture,
+            settings: PostProcessing::default(),
+        };
+        post_process.write_lut(queue, &post_process.settings.grading);
+        post_process
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        label: &str,
+        input_layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        width: u32,
+        height: u32,
+    ) -> [RenderTarget; 2] {
+        [(); 2].map(|_| RenderTarget::new(device, label, input_layout, sampler, width, height))
+    }
+
+    /// Recreates the ping-pong targets with the new size.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.targets = Self::create_targets(
+            device,
+            "post-processing",
+            &self.input_layout,
+            &self.sampler,
+            width,
+            height,
+        );
+        self.bloom.targets = Self::create_targets(
+            device,
+            "bloom",
+            &self.input_layout,
+            &self.sampler,
+            width / 2,
+            height / 2,
+        );
+        self.bloom.composite.rebind(
+            device,
+            &[wgpu::BindingResource::TextureView(
+                &self.bloom.targets[0].view,
+            )],
+        );
+    }
+
+    /// The target the scene is rendered to.
+    pub fn scene_view(&self) -> &wgpu::TextureView {
+        &self.targets[0].view
+    }
+
+    /// Uploads the parameters of all effects. `exposure` is given in stops.
+    pub fn prepare(
+        &mut self,
+        queue: &wgpu::Queue,
+        settings: &PostProcessing,
+        tonemapping: Tonemapping,
+        exposure: f32,
+    ) {
+        if settings.grading != self.settings.grading {
+            self.write_lut(queue, &settings.grading);
+        }
+        self.settings = *settings;
+
+        for (effect, direction) in [
+            (&self.bloom.extract, [0.0, 0.0]),
+            (&self.bloom.blur_horizontal, [1.0, 0.0]),
+            (&self.bloom.blur_vertical, [0.0, 1.0]),
+            (&self.bloom.composite, [0.0, 0.0]),
+        ] {
+            effect.write_params(
+                queue,
+                &BloomParams {
+                    threshold: settings.bloom_threshold,
+                    intensity: settings.bloom_intensity,
+                    direction,
+                },
+            );
+        }
+        self.tonemap.write_params(
+            queue,
+            &TonemapParams {
+                exposure: exposure.exp2(),
+                mode: tonemapping as u32,
+                _padding: [0; 2],
+            },
+        );
+        self.sharpen
+            .write_params(queue, &[settings.sharpen_strength, 0.0, 0.0, 0.0]);
+        // Strength, radius and softness
+        self.vignette
+            .write_params(queue, &[settings.vignette_strength, 0.4, 0.6, 0.0]);
+    }
+
+    /// Bakes the grading into the LUT, stored sRGB-encoded like its coordinates.
+    fn write_lut(&self, queue: &wgpu::Queue, grading: &ColorGrading) {
+        let n = Self::LUT_SIZE;
+        let mut texels = Vec::with_capacity((n * n * n * 4) as usize);
+        for b in 0..n {
+            for g in 0..n {
+                for r in 0..n {
+                    let encoded = [r, g, b].map(|c| c as f32 / (n - 1) as f32);
+                    let graded = grade(encoded, grading);
+                    texels.extend(graded.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8));
+                    texels.push(255);
+                }
+            }
+        }
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.lut_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &texels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * n),
+                rows_per_image: Some(n),
+            },
+            self.lut_texture.size(),
+        );
+    }
+
+    /// Runs the enabled effects on the scene target and writes the result to `output`.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let settings = &self.settings;
+
+        if settings.bloom {
+            let bloom = &self.bloom;
+            bloom.extract.draw(
+                encoder,
+                &self.targets[0].bind_group,
+                &bloom.targets[0].view,
+                false,
+            );
+            bloom.blur_horizontal.draw(
+                encoder,
+                &bloom.targets[0].bind_group,
+                &bloom.targets[1].view,
+                false,
+            );
+            bloom.blur_vertical.draw(
+                encoder,
+                &bloom.targets[1].bind_group,
+                &bloom.targets[0].view,
+                false,
+            );
+        }
+
+        let effects: Vec<&Effect> = [
+            (settings.bloom, &self.bloom.composite),
+            (true, &self.tonemap),
+            (settings.color_grading, &self.color_grading),
+            (settings.sharpen, &self.sharpen),
+            (settings.fxaa, &self.fxaa),
+            (settings.vignette, &self.vignette),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, effect)| enabled.then_some(effect))
+        .collect();
+
+        for (i, effect) in effects.iter().enumerate() {
+            let input = &self.targets[i % 2].bind_group;
+            if i + 1 == effects.len() {
+                effect.draw(encoder, input, output, true);
+            } else {
+                effect.draw(encoder, input, &self.targets[(i + 1) % 2].view, false);
+            }
+        }
+    }
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Grades an sRGB-encoded color and returns it sRGB-encoded.
+fn grade(encoded: [f32; 3], grading: &ColorGrading) -> [f32; 3] {
+    let [r, g, b] = encoded.map(srgb_to_linear);
+    let warmth = grading.temperature * 0.1;
+    let color = [r * (1.0 + warmth), g, b * (1.0 - warmth)];
+
+    let luma = 0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2];
+    let color = color.map(|c| (luma + (c - luma) * grading.saturation).max(0.0));
+
+    color.map(|c| (linear_to_srgb(c.min(1.0)) - 0.5) * grading.contrast + 0.5)
+}
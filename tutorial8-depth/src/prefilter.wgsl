@@ -0,0 +1,92 @@
+// Resampling and convolution of an environment cube map
+
+@group(1) @binding(0)
+var source_texture: texture_cube<f32>;
+@group(1) @binding(1)
+var source_sampler: sampler;
+
+// Copies the source, used for mip levels and converting the format
+@fragment
+fn fs_resample(in: FaceOutput) -> @location(0) vec4<f32> {
+    let direction = face_direction(params.face, in.st);
+    return vec4<f32>(textureSampleLevel(source_texture, source_sampler, direction, 0.0).rgb, 1.0);
+}
+
+const IRRADIANCE_DELTA: f32 = 0.05;
+
+// Cosine-weighted integral of the incoming light over the hemisphere
+@fragment
+fn fs_irradiance(in: FaceOutput) -> @location(0) vec4<f32> {
+    let normal = face_direction(params.face, in.st);
+    let helper = select(vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(0.0, 0.0, 1.0), abs(normal.y) > 0.999);
+    let right = normalize(cross(helper, normal));
+    let up = cross(normal, right);
+    // A blurry mip level suffices and avoids aliasing
+    let lod = max(log2(params.source_size / 32.0), 0.0);
+
+    var sum = vec3<f32>(0.0);
+    var count = 0.0;
+    for (var phi = 0.0; phi < 2.0 * PI; phi += IRRADIANCE_DELTA) {
+        for (var theta = 0.0; theta < 0.5 * PI; theta += IRRADIANCE_DELTA) {
+            let tangent = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let direction = tangent.x * right + tangent.y * up + tangent.z * normal;
+            let radiance = textureSampleLevel(source_texture, source_sampler, direction, lod).rgb;
+            sum += radiance * cos(theta) * sin(theta);
+            count += 1.0;
+        }
+    }
+    return vec4<f32>(PI * sum / count, 1.0);
+}
+
+const SPECULAR_SAMPLES: u32 = 256u;
+
+fn hammersley(i: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(SPECULAR_SAMPLES), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+
+fn importance_sample_ggx(xi: vec2<f32>, normal: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    let h = vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+
+    let helper = select(vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(1.0, 0.0, 0.0), abs(normal.y) > 0.999);
+    let tangent = normalize(cross(helper, normal));
+    let bitangent = cross(normal, tangent);
+    return normalize(tangent * h.x + bitangent * h.y + normal * h.z);
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a2 = roughness * roughness * roughness * roughness;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// GGX prefiltering for the split-sum approximation, assuming the view
+// direction equals the normal
+@fragment
+fn fs_specular(in: FaceOutput) -> @location(0) vec4<f32> {
+    let normal = face_direction(params.face, in.st);
+    let roughness = params.roughness;
+    // Solid angle of a texel of the source
+    let texel_angle = 4.0 * PI / (6.0 * params.source_size * params.source_size);
+
+    var sum = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < SPECULAR_SAMPLES; i++) {
+        let h = importance_sample_ggx(hammersley(i), normal, roughness);
+        let l = normalize(2.0 * dot(normal, h) * h - normal);
+        let n_dot_l = dot(normal, l);
+        if n_dot_l > 0.0 {
+            // Sample a mip level matching the footprint of the sample
+            let n_dot_h = max(dot(normal, h), 0.0);
+            let pdf = distribution_ggx(n_dot_h, roughness) * 0.25 + 0.0001;
+            let sample_angle = 1.0 / (f32(SPECULAR_SAMPLES) * pdf + 0.0001);
+            let lod = select(0.5 * log2(sample_angle / texel_angle), 0.0, roughness == 0.0);
+            sum += textureSampleLevel(source_texture, source_sampler, l, max(lod, 0.0)).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+    return vec4<f32>(sum / max(weight, 0.0001), 1.0);
+}
@@ -0,0 +1,475 @@
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use glam::Vec3;
+use instant::Instant;
+
+use crate::{InstanceRaw, MyTexture};
+
+/// Copies resolved query results into a mappable buffer and reads them back
+/// without stalling the frame.
+struct QueryReadback {
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    query_count: u32,
+    in_flight: bool,
+    /// Result of the last `map`, set by its callback
+    mapped: Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>,
+}
+
+impl QueryReadback {
+    fn new(device: &wgpu::Device, query_count: u32, values_per_query: u32) -> Self {
+        let size = (query_count * values_per_query) as u64 * std::mem::size_of::<u64>() as u64;
+        Self {
+            resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("query resolve"),
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                size,
+                mapped_at_creation: false,
+            }),
+            readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("query readback"),
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                size,
+                mapped_at_creation: false,
+            }),
+            query_count,
+            in_flight: false,
+            mapped: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Queries can only be written while the readback buffer is not in use.
+    fn is_idle(&self) -> bool {
+        !self.in_flight
+    }
+
+    fn resolve(&self, encoder: &mut wgpu::CommandEncoder, query_set: &wgpu::QuerySet) {
+        encoder.resolve_query_set(query_set, 0..self.query_count, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            self.resolve_buffer.size(),
+        );
+    }
+
+    /// Must be called after the command buffer from `resolve` is submitted.
+    fn map(&mut self) {
+        let mapped = self.mapped.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                *mapped.lock().unwrap() = Some(result);
+            });
+        self.in_flight = true;
+    }
+
+    /// Returns the query results once the previous `map` has completed.
+    ///
+    /// A failed `map` is logged and makes the readback idle again, so the
+    /// queries are written and mapped again on the next frames.
+    fn try_read(&mut self) -> Option<Vec<u64>> {
+        if !self.in_flight {
+            return None;
+        }
+        if let Err(err) = self.mapped.lock().unwrap().take()? {
+            log::error!("query readback failed: {}", err);
+            self.in_flight = false;
+            return None;
+        }
+        let values = {
+            let view = self.readback_buffer.slice(..).get_mapped_range();
+            bytemuck::cast_slice(&view).to_vec()
+        };
+        self.readback_buffer.unmap();
+        self.in_flight = false;
+        Some(values)
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ChunkBounds {
+    min: [f32; 3],
+    max: [f32; 3],
+}
+
+impl ChunkBounds {
+    fn layout() -> wgpu::VertexBufferLayout<'static> {
+        const ATRIBUTES: [wgpu::VertexAttribute; 2] =
+            wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ChunkBounds>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATRIBUTES,
+        }
+    }
+}
+
+/// Occlusion culling of instance chunks.
+///
+/// Every chunk is drawn inside its own occlusion query. Chunks that produced
+/// no samples are skipped on the next frame; instead, their bounding boxes are
+/// drawn (without color or depth writes) so that they get visible again as
+/// soon as they are uncovered.
+pub struct OcclusionCulling {
+    pub enabled: bool,
+    chunks: Vec<Range<u32>>,
+    visible: Vec<bool>,
+    bounds: Vec<ChunkBounds>,
+    bounds_buffer: wgpu::Buffer,
+    query_set: wgpu::QuerySet,
+    readback: QueryReadback,
+    proxy_pipeline: wgpu::RenderPipeline,
+    querying: bool,
+}
+
+impl OcclusionCulling {
+    pub fn new(
+        device: &wgpu::Device,
+        uniform_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        chunks: Vec<Range<u32>>,
+    ) -> Self {
+        // Bounding boxes only have to touch the depth buffer
+        let proxy_pipeline = {
+            let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("occlusion proxies"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("occlusion.wgsl").into()),
+            });
+
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("occlusion proxies"),
+                bind_group_layouts: &[uniform_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("occlusion proxies"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader_module,
+                    entry_point: "vs_main",
+                    buffers: &[ChunkBounds::layout()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader_module,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::empty(),
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    cull_mode: None,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: MyTexture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+            })
+        };
+
+        let count = chunks.len() as u32;
+        Self {
+            enabled: false,
+            visible: vec![true; chunks.len()],
+            bounds: vec![bytemuck::Zeroable::zeroed(); chunks.len()],
+            bounds_buffer: Self::create_bounds_buffer(device, count),
+            chunks,
+            query_set: Self::create_query_set(device, count),
+            readback: QueryReadback::new(device, count, 1),
+            proxy_pipeline,
+            querying: false,
+        }
+    }
+
+    fn create_query_set(device: &wgpu::Device, count: u32) -> wgpu::QuerySet {
+        device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("occlusion"),
+            ty: wgpu::QueryType::Occlusion,
+            count: count.max(1),
+        })
+    }
+
+    fn create_bounds_buffer(device: &wgpu::Device, count: u32) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("chunk bounds"),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            size: (std::mem::size_of::<ChunkBounds>() * count.max(1) as usize) as u64,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Replaces the chunks, e.g. after the instance grid was rebuilt.
+    pub fn set_chunks(&mut self, device: &wgpu::Device, chunks: Vec<Range<u32>>) {
+        let count = chunks.len() as u32;
+        self.query_set = Self::create_query_set(device, count);
+        self.bounds_buffer = Self::create_bounds_buffer(device, count);
+        self.readback = QueryReadback::new(device, count, 1);
+        self.visible = vec![true; chunks.len()];
+        self.bounds = vec![bytemuck::Zeroable::zeroed(); chunks.len()];
+        self.chunks = chunks;
+    }
+
+    pub fn query_set(&self) -> Option<&wgpu::QuerySet> {
+        self.querying.then_some(&self.query_set)
+    }
+
+    /// Picks up the visibility computed from an earlier frame and recomputes
+    /// the chunk bounds for this frame.
+    pub fn prepare(&mut self, queue: &wgpu::Queue, raws: &[InstanceRaw], eye: Vec3) {
+        if let Some(samples) = self.readback.try_read() {
+            for (visible, samples) in self.visible.iter_mut().zip(samples) {
+                *visible = samples > 0;
+            }
+        }
+
+        if !self.enabled {
+            self.visible.fill(true);
+            self.querying = false;
+            return;
+        }
+
+        for ((range, bounds), visible) in self
+            .chunks
+            .iter()
+            .zip(self.bounds.iter_mut())
+            .zip(self.visible.iter_mut())
+        {
+            // Quads are at most half their scale away from the instance origin
+            let (min, max) = raws[range.start as usize..range.end as usize]
+                .iter()
+                .map(|raw| {
+                    let position = Vec3::from_slice(&raw.matrix[12..15]);
+                    let extent = Vec3::splat(0.5 * raw.scale);
+                    (position - extent, position + extent)
+                })
+                .fold(
+                    (Vec3::INFINITY, Vec3::NEG_INFINITY),
+                    |(min, max), (lo, hi)| (min.min(lo), max.max(hi)),
+                );
+            *bounds = ChunkBounds {
+                min: min.to_array(),
+                max: max.to_array(),
+            };
+
+            // The bounding box cannot be tested from inside
+            if eye.cmpge(min).all() && eye.cmple(max).all() {
+                *visible = true;
+            }
+        }
+        queue.write_buffer(&self.bounds_buffer, 0, bytemuck::cast_slice(&self.bounds));
+
+        self.querying = self.readback.is_idle();
+    }
+
+    /// Draws the instances of every chunk that was visible on the last frame.
+    ///
+    /// The caller is expected to have set up the pipeline and buffers for
+    /// the instanced draw.
+    pub fn draw_visible<'a>(&self, render_pass: &mut wgpu::RenderPass<'a>, num_indices: u32) {
+        for (i, (range, _)) in self
+            .chunks
+            .iter()
+            .zip(&self.visible)
+            .enumerate()
+            .filter(|(_, (_, &visible))| visible)
+        {
+            if self.querying {
+                render_pass.begin_occlusion_query(i as u32);
+            }
+            render_pass.draw_indexed(0..num_indices, 0, range.clone());
+            if self.querying {
+                render_pass.end_occlusion_query();
+            }
+        }
+    }
+
+    /// Instance ranges of the chunks drawn by `draw_visible`.
+    pub fn visible_ranges(&self) -> impl Iterator<Item = Range<u32>> + '_ {
+        self.chunks
+            .iter()
+            .zip(&self.visible)
+            .filter(|(_, &visible)| visible)
+            .map(|(range, _)| range.clone())
+    }
+
+    /// Tests the bounding boxes of the hidden chunks against the depth buffer.
+    pub fn draw_proxies<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        uniform_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if !self.querying || self.visible.iter().all(|&v| v) {
+            return;
+        }
+
+        render_pass.set_pipeline(&self.proxy_pipeline);
+        render_pass.set_vertex_buffer(0, self.bounds_buffer.slice(..));
+        render_pass.set_bind_group(0, uniform_bind_group, &[]);
+        for (i, _) in self.visible.iter().enumerate().filter(|(_, &v)| !v) {
+            let i = i as u32;
+            render_pass.begin_occlusion_query(i);
+            render_pass.draw(0..36, i..i + 1);
+            render_pass.end_occlusion_query();
+        }
+    }
+
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        if self.querying {
+            self.readback.resolve(encoder, &self.query_set);
+        }
+    }
+
+    pub fn after_submit(&mut self) {
+        if self.querying {
+            self.readback.map();
+        }
+    }
+
+    pub fn visible_chunks(&self) -> usize {
+        self.visible.iter().filter(|&&v| v).count()
+    }
+
+    pub fn total_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+}
+
+/// Vertex and fragment shader invocation counters of the scene pass.
+pub struct PipelineStatistics {
+    query_set: wgpu::QuerySet,
+    readback: QueryReadback,
+    querying: bool,
+    pub latest: Option<PipelineStatisticsResult>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct PipelineStatisticsResult {
+    pub vertex_shader_invocations: u64,
+    pub clipper_primitives_out: u64,
+    pub fragment_shader_invocations: u64,
+}
+
+impl PipelineStatistics {
+    const TYPES: wgpu::PipelineStatisticsTypes =
+        wgpu::PipelineStatisticsTypes::VERTEX_SHADER_INVOCATIONS
+            .union(wgpu::PipelineStatisticsTypes::CLIPPER_PRIMITIVES_OUT)
+            .union(wgpu::PipelineStatisticsTypes::FRAGMENT_SHADER_INVOCATIONS);
+
+    /// Returns `None` if the device does not support pipeline statistics queries.
+    pub fn new(device: &wgpu::Device) -> Option<Self> {
+        if !device
+            .features()
+            .contains(wgpu::Features::PIPELINE_STATISTICS_QUERY)
+        {
+            return None;
+        }
+
+        Some(Self {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("pipeline statistics"),
+                ty: wgpu::QueryType::PipelineStatistics(Self::TYPES),
+                count: 1,
+            }),
+            // One query yields one value per statistics type
+            readback: QueryReadback::new(device, 1, 3),
+            querying: false,
+            latest: None,
+        })
+    }
+
+    pub fn prepare(&mut self) {
+        if let Some(values) = self.readback.try_read() {
+            // Values are written in the declaration order of the flags
+            self.latest = Some(PipelineStatisticsResult {
+                vertex_shader_invocations: values[0],
+                clipper_primitives_out: values[1],
+                fragment_shader_invocations: values[2],
+            });
+        }
+        self.querying = self.readback.is_idle();
+    }
+
+    pub fn begin<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.querying {
+            render_pass.begin_pipeline_statistics_query(&self.query_set, 0);
+        }
+    }
+
+    pub fn end(&self, render_pass: &mut wgpu::RenderPass) {
+        if self.querying {
+            render_pass.end_pipeline_statistics_query();
+        }
+    }
+
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        if self.querying {
+            self.readback.resolve(encoder, &self.query_set);
+        }
+    }
+
+    pub fn after_submit(&mut self) {
+        if self.querying {
+            self.readback.map();
+        }
+    }
+}
+
+/// Writes the query results to the debug log about once a second.
+pub struct QueryReport {
+    last: Instant,
+}
+
+impl QueryReport {
+    pub fn new() -> Self {
+        Self {
+            last: Instant::now(),
+        }
+    }
+
+    pub fn log(
+        &mut self,
+        occlusion: &OcclusionCulling,
+        statistics: Option<&PipelineStatistics>,
+        pixels: u32,
+    ) {
+        if self.last.elapsed().as_secs_f32() < 1.0 {
+            return;
+        }
+        self.last = Instant::now();
+
+        if occlusion.enabled {
+            log::debug!(
+                "occlusion culling: {}/{} chunks visible",
+                occlusion.visible_chunks(),
+                occlusion.total_chunks()
+            );
+        }
+        if let Some(result) = statistics.and_then(|s| s.latest) {
+            log::debug!(
+                "pipeline statistics: {} vertex invocations, {} primitives, {} fragment invocations ({:.2} per pixel)",
+                result.vertex_shader_invocations,
+                result.clipper_primitives_out,
+                result.fragment_shader_invocations,
+                result.fragment_shader_invocations as f64 / pixels.max(1) as f64,
+            );
+        }
+    }
+}
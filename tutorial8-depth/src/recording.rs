@@ -0,0 +1,170 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+/// Settings of a deterministic offscreen recording.
+#[derive(Clone, Debug)]
+pub struct RecordingOptions {
+    pub frames: u32,
+    pub fps: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Directory for the numbered PNG sequence
+    pub output_dir: PathBuf,
+    /// Optional raw YUV4MPEG2 stream
+    pub y4m: Option<PathBuf>,
+}
+
+impl Default for RecordingOptions {
+    fn default() -> Self {
+        Self {
+            frames: 300,
+            fps: 60,
+            width: 1280,
+            height: 720,
+            output_dir: PathBuf::from("frames"),
+            y4m: None,
+        }
+    }
+}
+
+/// Offscreen render target and writers for a recording.
+pub struct Recorder {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    output_dir: PathBuf,
+    y4m: Option<BufWriter<File>>,
+    frame: u32,
+}
+
+impl Recorder {
+    /// `format` has to match the color target of the scene pipeline.
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        options: &RecordingOptions,
+    ) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&options.output_dir)?;
+
+        let y4m = match &options.y4m {
+            Some(path) => {
+                let mut writer = BufWriter::new(File::create(path)?);
+                write_y4m_header(&mut writer, options)?;
+                Some(writer)
+            }
+            None => None,
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("recording target"),
+            size: wgpu::Extent3d {
+                width: options.width,
+                height: options.height,
+                depth_or_array_layers: 1,
+            },
+            dimension: wgpu::TextureDimension::D2,
+            mip_level_count: 1,
+            sample_count: 1,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        Ok(Self {
+            view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            texture,
+            output_dir: options.output_dir.clone(),
+            y4m,
+            frame: 0,
+        })
+    }
+
+    pub fn write_frame(&mut self, image: &image::RgbaImage) -> std::io::Result<()> {
+        let path = self.output_dir.join(format!("frame_{:05}.png", self.frame));
+        image.save(&path).map_err(std::io::Error::other)?;
+
+        if let Some(writer) = &mut self.y4m {
+            write_y4m_frame(writer, image)?;
+        }
+
+        self.frame += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> std::io::Result<()> {
+        if let Some(writer) = &mut self.y4m {
+            writer.flush()?;
+        }
+        log::info!(
+            "recorded {} frames to {}",
+            self.frame,
+            self.output_dir.display()
+        );
+        Ok(())
+    }
+}
+
+/// Starts the stream with the size and frame rate of the recording.
+fn write_y4m_header(writer: &mut impl Write, options: &RecordingOptions) -> std::io::Result<()> {
+    // Full resolution chroma keeps the conversion trivial, the range has to
+    // be given as players assume the limited one
+    writeln!(
+        writer,
+        "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444 XCOLORRANGE=FULL",
+        options.width, options.height, options.fps
+    )
+}
+
+/// Appends one frame converted to full-range BT.601 YCbCr planes.
+fn write_y4m_frame(writer: &mut impl Write, image: &image::RgbaImage) -> std::io::Result<()> {
+    let len = image.pixels().len();
+    let mut planes = vec![0u8; len * 3];
+    let (y_plane, rest) = planes.split_at_mut(len);
+    let (u_plane, v_plane) = rest.split_at_mut(len);
+
+    for (i, pixel) in image.pixels().enumerate() {
+        let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|v| v as f32);
+        let y = 0.299 * r + 0.587 * g + 0.114 * b;
+        y_plane[i] = y.round().clamp(0.0, 255.0) as u8;
+        u_plane[i] = ((b - y) * 0.564 + 128.0).round().clamp(0.0, 255.0) as u8;
+        v_plane[i] = ((r - y) * 0.713 + 128.0).round().clamp(0.0, 255.0) as u8;
+    }
+
+    writer.write_all(b"FRAME\n")?;
+    writer.write_all(&planes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_describes_the_recording() {
+        let options = RecordingOptions {
+            width: 320,
+            height: 240,
+            fps: 30,
+            ..Default::default()
+        };
+        let mut stream = Vec::new();
+        write_y4m_header(&mut stream, &options).unwrap();
+        assert_eq!(
+            stream,
+            b"YUV4MPEG2 W320 H240 F30:1 Ip A1:1 C444 XCOLORRANGE=FULL\n"
+        );
+    }
+
+    #[test]
+    fn frame_is_written_as_full_planes() {
+        let image =
+            image::RgbaImage::from_raw(3, 1, vec![255, 0, 0, 255, 255, 255, 255, 255, 0, 0, 0, 0])
+                .unwrap();
+        let mut stream = Vec::new();
+        write_y4m_frame(&mut stream, &image).unwrap();
+
+        let (tag, planes) = stream.split_at(6);
+        assert_eq!(tag, b"FRAME\n");
+        // Y, then Cb and Cr, for red, white and transparent black
+        assert_eq!(planes, [76, 255, 0, 85, 128, 128, 255, 128, 128]);
+    }
+}
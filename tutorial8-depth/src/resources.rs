@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops::Index;
+
+/// Typed reference to a resource owned by [`Resources`].
+///
+/// A handle to a released resource is stale and panics on use, also if its
+/// slot was reused by another resource.
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> std::fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Handle({}v{})", self.index, self.generation)
+    }
+}
+
+struct Slot<T> {
+    generation: u32,
+    label: String,
+    value: Option<T>,
+}
+
+/// Resources of one type, in slots reused after a release.
+pub struct Storage<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+}
+
+impl<T> Default for Storage<T> {
+    fn default() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+}
+
+impl<T> Storage<T> {
+    fn insert(&mut self, label: &str, value: T) -> Handle<T> {
+        let index = match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.generation += 1;
+                slot.label = label.to_string();
+                slot.value = Some(value);
+                index
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    label: label.to_string(),
+                    value: Some(value),
+                });
+                self.slots.len() as u32 - 1
+            }
+        };
+        Handle {
+            index,
+            generation: self.slots[index as usize].generation,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Drops the value and frees the slot, `None` if `handle` is stale.
+    fn remove(&mut self, handle: Handle<T>) -> Option<T> {
+        let value = self.slot_mut(handle)?.value.take();
+        self.free.push(handle.index);
+        value
+    }
+
+    fn slot(&self, handle: Handle<T>) -> Option<&Slot<T>> {
+        self.slots
+            .get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation && slot.value.is_some())
+    }
+
+    fn slot_mut(&mut self, handle: Handle<T>) -> Option<&mut Slot<T>> {
+        self.slots
+            .get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation && slot.value.is_some())
+    }
+}
+
+/// GPU resource types owned by [`Resources`].
+pub trait Managed: Sized + 'static {
+    const KIND: &'static str;
+    fn storage(resources: &Resources) -> &Storage<Self>;
+    fn storage_mut(resources: &mut Resources) -> &mut Storage<Self>;
+}
+
+macro_rules! managed {
+    ($($field:ident: $ty:ty,)*) => {
+        /// Owns the GPU resources of the scene behind typed [`Handle`]s.
+        ///
+        /// These are the scene's buffers, textures, bind groups, shader and
+        /// pipelines. The passes in their own modules (post-processing, OIT,
+        /// overdraw, ID picking, GPU sorting, occlusion culling, text, skybox
+        /// and environment) still own their resources.
+        ///
+        /// Resources are created with a label, which is also given to wgpu for
+        /// its validation errors and graphics debuggers. Samplers and bind group
+        /// layouts with equal descriptors are shared.
+        #[derive(Default)]
+        pub struct Resources {
+            $($field: Storage<$ty>,)*
+            samplers_by_key: HashMap<SamplerKey, Handle<wgpu::Sampler>>,
+            bind_group_layouts_by_entries:
+                HashMap<Vec<wgpu::BindGroupLayoutEntry>, Handle<wgpu::BindGroupLayout>>,
+        }
+
+        $(
+            impl Managed for $ty {
+                const KIND: &'static str = stringify!($field);
+
+                fn storage(resources: &Resources) -> &Storage<Self> {
+                    &resources.$field
+                }
+
+                fn storage_mut(resources: &mut Resources) -> &mut Storage<Self> {
+                    &mut resources.$field
+                }
+            }
+        )*
+
+        impl Resources {
+            /// Lists the live resources by type with their labels.
+            pub fn dump(&self) -> String {
+                let mut dump = String::from("resources:\n");
+                $(self.dump_storage(&mut dump, &self.$field);)*
+                dump
+            }
+        }
+    };
+}
+
+managed! {
+    buffers: wgpu::Buffer,
+    texture_views: wgpu::TextureView,
+    samplers: wgpu::Sampler,
+    bind_group_layouts: wgpu::BindGroupLayout,
+    bind_groups: wgpu::BindGroup,
+    shader_modules: wgpu::ShaderModule,
+    pipeline_layouts: wgpu::PipelineLayout,
+    render_pipelines: wgpu::RenderPipeline,
+    compute_pipelines: wgpu::ComputePipeline,
+}
+
+/// The hashable parts of a `wgpu::SamplerDescriptor`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct SamplerKey {
+    address_modes: [wgpu::AddressMode; 3],
+    filters: [wgpu::FilterMode; 3],
+    lod_clamp: [u32; 2],
+    compare: Option<wgpu::CompareFunction>,
+    anisotropy_clamp: u16,
+    border_color: Option<wgpu::SamplerBorderColor>,
+}
+
+impl SamplerKey {
+    fn new(desc: &wgpu::SamplerDescriptor) -> Self {
+        Self {
+            address_modes: [
+                desc.address_mode_u,
+                desc.address_mode_v,
+                desc.address_mode_w,
+            ],
+            filters: [desc.mag_filter, desc.min_filter, desc.mipmap_filter],
+            lod_clamp: [desc.lod_min_clamp.to_bits(), desc.lod_max_clamp.to_bits()],
+            compare: desc.compare,
+            anisotropy_clamp: desc.anisotropy_clamp,
+            border_color: desc.border_color,
+        }
+    }
+}
+
+impl Resources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores the resource made by `create`, which is given the resources to
+    /// look up other handles and the label for its descriptor.
+    pub fn create<T: Managed>(
+        &mut self,
+        label: &str,
+        create: impl FnOnce(&Self, Option<&str>) -> T,
+    ) -> Handle<T> {
+        let value = create(self, Some(label));
+        T::storage_mut(self).insert(label, value)
+    }
+
+    /// Replaces the resource behind `handle` under the same label, e.g. to
+    /// reload a texture. Bind groups using the old resource keep it alive
+    /// until they are replaced too.
+    pub fn replace<T: Managed>(
+        &mut self,
+        handle: Handle<T>,
+        create: impl FnOnce(&Self, Option<&str>) -> T,
+    ) {
+        let label = self.label(handle).to_string();
+        let value = create(self, Some(&label));
+        Self::expect_slot_mut(self, handle).value = Some(value);
+    }
+
+    /// Drops the resource, making `handle` stale.
+    pub fn release<T: Managed>(&mut self, handle: Handle<T>) {
+        if T::storage_mut(self).remove(handle).is_none() {
+            panic!("stale handle to {} {:?}", T::KIND, handle);
+        }
+    }
+
+    pub fn label<T: Managed>(&self, handle: Handle<T>) -> &str {
+        &self.expect_slot(handle).label
+    }
+
+    /// A sampler for `desc`, shared with earlier requests for an equal one.
+    pub fn sampler(
+        &mut self,
+        device: &wgpu::Device,
+        desc: &wgpu::SamplerDescriptor,
+    ) -> Handle<wgpu::Sampler> {
+        let key = SamplerKey::new(desc);
+        if let Some(&handle) = self.samplers_by_key.get(&key) {
+            if self.samplers.slot(handle).is_some() {
+                return handle;
+            }
+        }
+        let handle = self.create(desc.label.unwrap_or("sampler"), |_, label| {
+            device.create_sampler(&wgpu::SamplerDescriptor {
+                label,
+                ..desc.clone()
+            })
+        });
+        self.samplers_by_key.insert(key, handle);
+        handle
+    }
+
+    /// A bind group layout with `entries`, shared with earlier requests for
+    /// the same entries.
+    pub fn bind_group_layout(
+        &mut self,
+        device: &wgpu::Device,
+        label: &str,
+        entries: &[wgpu::BindGroupLayoutEntry],
+    ) -> Handle<wgpu::BindGroupLayout> {
+        if let Some(&handle) = self.bind_group_layouts_by_entries.get(entries) {
+            if self.bind_group_layouts.slot(handle).is_some() {
+                return handle;
+            }
+        }
+        let handle = self.create(label, |_, label| {
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor { label, entries })
+        });
+        self.bind_group_layouts_by_entries
+            .insert(entries.to_vec(), handle);
+        handle
+    }
+
+    fn expect_slot<T: Managed>(&self, handle: Handle<T>) -> &Slot<T> {
+        T::storage(self)
+            .slot(handle)
+            .unwrap_or_else(|| panic!("stale handle to {} {:?}", T::KIND, handle))
+    }
+
+    fn expect_slot_mut<T: Managed>(&mut self, handle: Handle<T>) -> &mut Slot<T> {
+        T::storage_mut(self)
+            .slot_mut(handle)
+            .unwrap_or_else(|| panic!("stale handle to {} {:?}", T::KIND, handle))
+    }
+
+    fn dump_storage<T: Managed>(&self, dump: &mut String, storage: &Storage<T>) {
+        let labels: Vec<&str> = storage
+            .slots
+            .iter()
+            .filter(|slot| slot.value.is_some())
+            .map(|slot| slot.label.as_str())
+            .collect();
+        let _ = writeln!(
+            dump,
+            "  {} ({}): {}",
+            T::KIND,
+            labels.len(),
+            labels.join(", ")
+        );
+    }
+}
+
+impl<T: Managed> Index<Handle<T>> for Resources {
+    type Output = T;
+
+    fn index(&self, handle: Handle<T>) -> &T {
+        self.expect_slot(handle).value.as_ref().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A device for the tests creating wgpu objects, `None` without an adapter.
+    fn device() -> Option<wgpu::Device> {
+        let instance = wgpu::Instance::default();
+        let adapter = pollster::block_on(instance.request_adapter(&Default::default()))?;
+        let (device, _) =
+            pollster::block_on(adapter.request_device(&Default::default(), None)).ok()?;
+        Some(device)
+    }
+
+    #[test]
+    fn reused_slot_rejects_the_stale_handle() {
+        let mut storage = Storage::default();
+        let first = storage.insert("first", 1);
+        assert_eq!(storage.remove(first), Some(1));
+        assert_eq!(storage.remove(first), None);
+
+        let second = storage.insert("second", 2);
+        assert_eq!(second.index, first.index);
+        assert_ne!(second, first);
+        assert!(storage.slot(first).is_none());
+        assert_eq!(storage.slot(second).unwrap().label, "second");
+    }
+
+    #[test]
+    fn shares_equal_samplers() {
+        let Some(device) = device() else {
+            eprintln!("no adapter, skipped");
+            return;
+        };
+        let mut resources = Resources::new();
+        let linear = wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        };
+        let first = resources.sampler(&device, &linear);
+        let relabeled = wgpu::SamplerDescriptor {
+            label: Some("other"),
+            ..linear.clone()
+        };
+        assert_eq!(resources.sampler(&device, &relabeled), first);
+        assert_ne!(resources.sampler(&device, &Default::default()), first);
+
+        // A released sampler is created again
+        resources.release(first);
+        let recreated = resources.sampler(&device, &linear);
+        assert_ne!(recreated, first);
+        assert_eq!(resources.sampler(&device, &linear), recreated);
+    }
+
+    #[test]
+    fn shares_equal_bind_group_layouts() {
+        let Some(device) = device() else {
+            eprintln!("no adapter, skipped");
+            return;
+        };
+        let mut resources = Resources::new();
+        let uniform = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let first = resources.bind_group_layout(&device, "first", &[uniform(0)]);
+        assert_eq!(
+            resources.bind_group_layout(&device, "second", &[uniform(0)]),
+            first
+        );
+        assert_eq!(resources.label(first), "first");
+        assert_ne!(
+            resources.bind_group_layout(&device, "third", &[uniform(1)]),
+            first
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::mpsc;
+
+/// A texture copied into a mappable buffer, waiting to be read back.
+pub struct TextureReadback {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    padded_bytes_per_row: u32,
+    format: wgpu::TextureFormat,
+}
+
+/// Why a [`TextureReadback`] has no image.
+#[derive(Debug)]
+pub enum ReadbackError {
+    Map(wgpu::BufferAsyncError),
+    UnsupportedFormat(wgpu::TextureFormat),
+}
+
+impl fmt::Display for ReadbackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Map(err) => write!(f, "cannot map the readback buffer: {}", err),
+            Self::UnsupportedFormat(format) => write!(f, "cannot convert {:?} pixels", format),
+        }
+    }
+}
+
+impl std::error::Error for ReadbackError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Map(err) => Some(err),
+            Self::UnsupportedFormat(_) => None,
+        }
+    }
+}
+
+impl TextureReadback {
+    /// Encodes a copy of `texture` (which needs `COPY_SRC` usage) into a
+    /// buffer. Rows are padded to `COPY_BYTES_PER_ROW_ALIGNMENT`.
+    pub fn encode(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) -> Self {
+        let (width, height, format) = (texture.width(), texture.height(), texture.format());
+        let bytes_per_pixel = format
+            .block_copy_size(None)
+            .expect("Color formats have a block size");
+        let padded_bytes_per_row =
+            (width * bytes_per_pixel).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback"),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            size: (padded_bytes_per_row * height) as u64,
+            mapped_at_creation: false,
+        });
+
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        Self {
+            buffer,
+            width,
+            height,
+            padded_bytes_per_row,
+            format,
+        }
+    }
+
+    /// Waits for the copy to finish and converts the pixels to 8-bit sRGB RGBA.
+    ///
+    /// Must be called after the command buffer from `encode` is submitted.
+    pub fn read(self, device: &wgpu::Device) -> Result<image::RgbaImage, ReadbackError> {
+        let bytes_per_pixel = match self.format {
+            wgpu::TextureFormat::Rgba8Unorm
+            | wgpu::TextureFormat::Rgba8UnormSrgb
+            | wgpu::TextureFormat::Bgra8Unorm
+            | wgpu::TextureFormat::Bgra8UnormSrgb => 4,
+            wgpu::TextureFormat::Rgba16Float => 8,
+            format => return Err(ReadbackError::UnsupportedFormat(format)),
+        };
+
+        let slice = self.buffer.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .unwrap_or(Err(wgpu::BufferAsyncError))
+            .map_err(ReadbackError::Map)?;
+
+        let mut pixels = Vec::with_capacity((self.width * self.height * 4) as usize);
+        {
+            let view = slice.get_mapped_range();
+            for row in view.chunks(self.padded_bytes_per_row as usize) {
+                let row = &row[..self.width as usize * bytes_per_pixel];
+                match self.format {
+                    wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => {
+                        pixels.extend_from_slice(row);
+                    }
+                    wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => {
+                        for bgra in row.chunks(4) {
+                            pixels.extend_from_slice(&[bgra[2], bgra[1], bgra[0], bgra[3]]);
+                        }
+                    }
+                    _ => {
+                        // Linear Rgba16Float; encode it like an sRGB target would
+                        for rgba in bytemuck::cast_slice::<u8, u16>(row).chunks(4) {
+                            let [r, g, b, a] = [rgba[0], rgba[1], rgba[2], rgba[3]].map(f16_to_f32);
+                            pixels.extend_from_slice(&[
+                                linear_to_srgb(r),
+                                linear_to_srgb(g),
+                                linear_to_srgb(b),
+                                (a.clamp(0.0, 1.0) * 255.0).round() as u8,
+                            ]);
+                        }
+                    }
+                }
+            }
+        }
+        self.buffer.unmap();
+
+        Ok(image::RgbaImage::from_raw(self.width, self.height, pixels)
+            .expect("Pixels should fill the image"))
+    }
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let encoded = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * 255.0).round() as u8
+}
+
+/// Saves `image` as `<prefix>-<UTC date>_<time>.png` in the working directory.
+pub fn save_timestamped(image: &image::RgbaImage, prefix: &str) -> image::ImageResult<PathBuf> {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let path = PathBuf::from(format!("{}-{}.png", prefix, timestamp(secs)));
+    image.save(&path)?;
+    Ok(path)
+}
+
+/// Formats seconds since the epoch as the UTC `<date>_<time>`, e.g.
+/// `2024-02-29_13-05-09`.
+fn timestamp(secs: u64) -> String {
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // Convert days since the epoch to a civil date (Howard Hinnant's algorithm)
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_known_dates() {
+        assert_eq!(timestamp(0), "1970-01-01_00-00-00");
+        assert_eq!(timestamp(1709214309), "2024-02-29_13-45-09");
+        assert_eq!(timestamp(4107542400), "2100-03-01_00-00-00");
+    }
+
+    #[test]
+    fn formats_leap_days() {
+        assert_eq!(timestamp(951782400), "2000-02-29_00-00-00");
+        assert_eq!(timestamp(951868800), "2000-03-01_00-00-00");
+        // 2100 is not a leap year
+        assert_eq!(timestamp(4107542400 - 1), "2100-02-28_23-59-59");
+    }
+
+    #[test]
+    fn formats_year_boundaries() {
+        assert_eq!(timestamp(1704067199), "2023-12-31_23-59-59");
+        assert_eq!(timestamp(1704067200), "2024-01-01_00-00-00");
+    }
+
+    #[test]
+    fn converts_f16_special_values() {
+        assert_eq!(f16_to_f32(0x0000), 0.0);
+        assert!(f16_to_f32(0x8000).is_sign_negative());
+        assert_eq!(f16_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_to_f32(0xc000), -2.0);
+        assert_eq!(f16_to_f32(0x7bff), 65504.0);
+        // Smallest and largest subnormal
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(f16_to_f32(0x03ff), 1023.0 * 2f32.powi(-24));
+        assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+        assert_eq!(f16_to_f32(0xfc00), f32::NEG_INFINITY);
+        assert!(f16_to_f32(0x7e00).is_nan());
+        assert!(f16_to_f32(0xfc01).is_nan());
+    }
+}
@@ -0,0 +1,327 @@
+/// Coefficients of the wave animating the instance grid.
+///
+/// An instance at distance `d` from the origin is lifted by a ripple
+/// `sin(d + t * ripple_speed) * d * ripple_amplitude` and a swell
+/// `(d / swell_radius) ^ swell_exponent * sin(t * swell_speed) * swell_amplitude`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Wave {
+    pub ripple_speed: f32,
+    pub ripple_amplitude: f32,
+    pub swell_radius: f32,
+    pub swell_exponent: f32,
+    pub swell_speed: f32,
+    pub swell_amplitude: f32,
+}
+
+impl Default for Wave {
+    fn default() -> Self {
+        Self {
+            ripple_speed: 10.0,
+            ripple_amplitude: 0.1,
+            swell_radius: 100.0,
+            swell_exponent: 3.0,
+            swell_speed: 1.0,
+            swell_amplitude: 10.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SamplerFilters {
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    pub mipmap_filter: wgpu::FilterMode,
+}
+
+impl Default for SamplerFilters {
+    fn default() -> Self {
+        Self {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+        }
+    }
+}
+
+/// Operator mapping the HDR scene to the displayable range.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Tonemapping {
+    /// Clamps to `[0, 1]`
+    #[default]
+    Linear = 0,
+    Reinhard = 1,
+    Aces = 2,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ColorGrading {
+    pub contrast: f32,
+    pub saturation: f32,
+    /// Shifts the white balance towards warm (positive) or cold (negative) colors
+    pub temperature: f32,
+}
+
+impl Default for ColorGrading {
+    fn default() -> Self {
+        Self {
+            contrast: 1.0,
+            saturation: 1.0,
+            temperature: 0.0,
+        }
+    }
+}
+
+/// Full-screen effects applied between the scene and the surface, in this order.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PostProcessing {
+    pub bloom: bool,
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+    pub color_grading: bool,
+    pub grading: ColorGrading,
+    pub sharpen: bool,
+    pub sharpen_strength: f32,
+    pub fxaa: bool,
+    pub vignette: bool,
+    pub vignette_strength: f32,
+}
+
+impl Default for PostProcessing {
+    fn default() -> Self {
+        Self {
+            bloom: false,
+            bloom_threshold: 1.0,
+            bloom_intensity: 0.5,
+            color_grading: false,
+            grading: ColorGrading::default(),
+            sharpen: false,
+            sharpen_strength: 0.3,
+            fxaa: false,
+            vignette: false,
+            vignette_strength: 0.5,
+        }
+    }
+}
+
+/// How the texture alpha of a material is handled.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Transparency {
+    /// Drawn opaque with depth writes, transparent texels included
+    #[default]
+    Opaque,
+    /// Texels below the alpha cutoff are discarded, the rest is opaque
+    Cutout,
+    /// The alpha selects the covered MSAA samples
+    AlphaToCoverage,
+    /// Alpha blended from back to front
+    Sorted,
+    /// Weighted blended order-independent transparency, without sorting
+    WeightedBlended,
+}
+
+impl Transparency {
+    /// Blended modes draw without writing depth.
+    pub fn is_blended(self) -> bool {
+        matches!(self, Self::Sorted | Self::WeightedBlended)
+    }
+
+    /// Modes discarding texels below [`Material::alpha_cutoff`] when drawn:
+    /// cutout, also in the depth pre-pass, and alpha to coverage as its
+    /// fallback without MSAA.
+    pub fn uses_alpha_cutoff(self) -> bool {
+        matches!(self, Self::Cutout | Self::AlphaToCoverage)
+    }
+
+    /// Modes discarding texels below [`Material::alpha_cutoff`] when picking
+    /// from the ID buffer, which has no blending to fade them.
+    pub fn picks_alpha_cutoff(self) -> bool {
+        self != Self::Opaque
+    }
+}
+
+/// How clicked instances are found.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Picking {
+    /// Ray cast against bounding spheres on the CPU
+    #[default]
+    RayCast,
+    /// Read back from an ID buffer rendered on the GPU, exact but a frame late
+    IdBuffer,
+}
+
+/// Surface properties of the instanced quads.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Material {
+    pub transparency: Transparency,
+    /// Alpha below which texels are discarded, see
+    /// [`Transparency::uses_alpha_cutoff`] and
+    /// [`Transparency::picks_alpha_cutoff`]
+    pub alpha_cutoff: f32,
+}
+
+impl Material {
+    /// Cutoff for the shaders, zero keeps all texels of opaque instances.
+    /// The blended modes only read it in the ID pass.
+    pub fn shader_alpha_cutoff(&self) -> f32 {
+        if self.transparency.picks_alpha_cutoff() {
+            self.alpha_cutoff
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            transparency: Transparency::default(),
+            alpha_cutoff: 0.5,
+        }
+    }
+}
+
+/// Per-instance attributes set from the settings.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct InstanceStyle {
+    /// Linear RGB
+    pub tint: [f32; 3],
+    pub scale: f32,
+    /// Color the instances by their height
+    pub color_by_height: bool,
+    /// Heights from `-height_range` to `height_range` span the color ramp
+    pub height_range: f32,
+}
+
+impl Default for InstanceStyle {
+    fn default() -> Self {
+        Self {
+            tint: [1.0; 3],
+            scale: 1.0,
+            color_by_height: false,
+            height_range: 10.0,
+        }
+    }
+}
+
+/// Depth-only pass drawing the opaque instances before shading them, so that
+/// every pixel is shaded once.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DepthPrepass {
+    #[default]
+    Off,
+    /// The color pass shades the fragments with exactly the pre-pass depth
+    Equal,
+    /// Tolerates differences of the depth between the passes
+    LessEqual,
+}
+
+impl DepthPrepass {
+    /// Depth test of the color pass after the pre-pass.
+    pub fn compare(self) -> Option<wgpu::CompareFunction> {
+        match self {
+            Self::Off => None,
+            Self::Equal => Some(wgpu::CompareFunction::Equal),
+            Self::LessEqual => Some(wgpu::CompareFunction::LessEqual),
+        }
+    }
+}
+
+/// Rasterizer state of the instances, for debugging meshes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rasterization {
+    /// Draw the triangle edges only
+    pub wireframe: bool,
+    pub cull_mode: Option<wgpu::Face>,
+    /// Winding of the front faces
+    pub front_face: wgpu::FrontFace,
+}
+
+impl Default for Rasterization {
+    fn default() -> Self {
+        Self {
+            wireframe: false,
+            cull_mode: None,
+            front_face: wgpu::FrontFace::Ccw,
+        }
+    }
+}
+
+impl Rasterization {
+    /// Wireframes use line rasterization if `polygon_mode_line` is supported,
+    /// otherwise the triangles are filled and the shader discards their insides.
+    pub fn primitive_state(self, polygon_mode_line: bool) -> wgpu::PrimitiveState {
+        wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: self.front_face,
+            cull_mode: self.cull_mode,
+            unclipped_depth: false,
+            polygon_mode: if self.wireframe && polygon_mode_line {
+                wgpu::PolygonMode::Line
+            } else {
+                wgpu::PolygonMode::Fill
+            },
+            conservative: false,
+        }
+    }
+}
+
+/// Scene parameters that can be changed at runtime.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Settings {
+    /// The grid spans `-grid_size..=grid_size` on both axes
+    pub grid_size: i32,
+    /// Linear RGB
+    pub clear_color: [f32; 3],
+    /// Draw the skybox instead of the clear color
+    pub skybox: bool,
+    /// Blend factor of the reflected environment
+    pub reflectivity: f32,
+    /// Blurs the reflections, selects the mip level of the prefiltered environment
+    pub roughness: f32,
+    /// Light the scene with the irradiance of the environment
+    pub ibl: bool,
+    pub material: Material,
+    /// Sort the instances with compute shaders instead of on the CPU
+    pub gpu_sort: bool,
+    pub depth_prepass: DepthPrepass,
+    pub picking: Picking,
+    pub instance_style: InstanceStyle,
+    pub rasterization: Rasterization,
+    pub wave: Wave,
+    pub sampler_filters: SamplerFilters,
+    pub tonemapping: Tonemapping,
+    /// Exposure in stops
+    pub exposure: f32,
+    pub post_processing: PostProcessing,
+    /// Show the fragments drawn per pixel instead of the scene
+    pub overdraw: bool,
+    /// Fragments per pixel at the top of the overdraw color ramp
+    pub overdraw_max_count: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            grid_size: 300,
+            clear_color: [0.0, 0.06, 0.1],
+            skybox: false,
+            reflectivity: 0.0,
+            roughness: 0.2,
+            ibl: false,
+            material: Material::default(),
+            gpu_sort: false,
+            depth_prepass: DepthPrepass::default(),
+            picking: Picking::default(),
+            instance_style: InstanceStyle::default(),
+            rasterization: Rasterization::default(),
+            wave: Wave::default(),
+            sampler_filters: SamplerFilters::default(),
+            tonemapping: Tonemapping::default(),
+            exposure: 0.0,
+            post_processing: PostProcessing::default(),
+            overdraw: false,
+            overdraw_max_count: 16.0,
+        }
+    }
+}
@@ -2,6 +2,16 @@
 
 struct WorldUniform {
     view_proj: mat4x4<f32>,
+    eye: vec3<f32>,
+    reflectivity: f32,
+    roughness: f32,
+    // Non-zero to light the albedo with the irradiance of the environment
+    ibl: u32,
+    // Alpha below which fs_cutout, fs_id and the passes counting or writing
+    // their depth discard, zero when opaque
+    alpha_cutoff: f32,
+    // Non-zero to discard all but the triangle edges
+    wireframe: u32,
 }
 
 @group(0) @binding(0)
@@ -17,15 +27,52 @@ struct InstanceInput {
     @location(3) model_mat_1: vec4<f32>,
     @location(4) model_mat_2: vec4<f32>,
     @location(5) model_mat_3: vec4<f32>,
+    @location(6) tint: vec4<f32>,
+    @location(7) layer: u32,
+    @location(8) scale: f32,
+    @location(9) value: f32,
+    @location(10) flags: u32,
 }
 
+// Bits of InstanceInput.flags
+const INSTANCE_COLOR_MAP: u32 = 1u;
+const INSTANCE_HIGHLIGHT: u32 = 2u;
+
 struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
+    // The depth pre-pass has to compute the same depth as the color pass
+    @builtin(position) @invariant clip_position: vec4<f32>,
     @location(1) tex_coord: vec2<f32>,
+    @location(2) world_position: vec3<f32>,
+    @location(3) normal: vec3<f32>,
+    @location(4) @interpolate(flat) layer: u32,
+    @location(5) tint: vec4<f32>,
+    // Position in the instance buffer
+    @location(6) @interpolate(flat) instance: u32,
+    @location(7) barycentric: vec3<f32>,
+}
+
+// Blue to green to yellow to red
+fn color_ramp(value: f32) -> vec3<f32> {
+    let x = saturate(value) * 3.0;
+    let blue = vec3<f32>(0.1, 0.2, 0.9);
+    let green = vec3<f32>(0.1, 0.8, 0.3);
+    let yellow = vec3<f32>(1.0, 0.9, 0.1);
+    let red = vec3<f32>(0.9, 0.1, 0.1);
+    if x < 1.0 {
+        return mix(blue, green, x);
+    } else if x < 2.0 {
+        return mix(green, yellow, x - 1.0);
+    }
+    return mix(yellow, red, x - 2.0);
 }
 
 @vertex
-fn vs_main(vert: VertexInput, inst: InstanceInput) -> VertexOutput {
+fn vs_main(
+    vert: VertexInput,
+    inst: InstanceInput,
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
     var out: VertexOutput;
 
     let model_mat = mat4x4<f32>(
@@ -35,19 +82,178 @@ fn vs_main(vert: VertexInput, inst: InstanceInput) -> VertexOutput {
         inst.model_mat_3
     );
 
-    out.clip_position = world.view_proj * model_mat * vec4<f32>(vert.position.xyz, 1.0);
+    let world_position = model_mat * vec4<f32>(vert.position.xyz * inst.scale, 1.0);
+    out.clip_position = world.view_proj * world_position;
     out.tex_coord = vert.tex_coord;
+    out.layer = inst.layer;
+    out.instance = instance_index;
+    // Only meaningful for the unindexed triangles of barycentric wireframes
+    let corner = vec3<u32>(vertex_index % 3u);
+    out.barycentric = select(vec3<f32>(0.0), vec3<f32>(1.0), corner == vec3<u32>(0u, 1u, 2u));
+    out.tint = inst.tint;
+    if (inst.flags & INSTANCE_COLOR_MAP) != 0u {
+        out.tint *= vec4<f32>(color_ramp(inst.value), 1.0);
+    }
+    if (inst.flags & INSTANCE_HIGHLIGHT) != 0u {
+        // Bright enough to bloom
+        out.tint = vec4<f32>(4.0, 2.0, 0.5, out.tint.a);
+    }
+    out.world_position = world_position.xyz;
+    // The quads lie in the xy plane
+    out.normal = (model_mat * vec4<f32>(0.0, 0.0, 1.0, 0.0)).xyz;
     return out;
 }
  
 
 @group(1) @binding(0)
-var color_texture: texture_2d<f32>;
+var color_texture: texture_2d_array<f32>;
 @group(1) @binding(1)
 var color_sampler: sampler;
 
+@group(2) @binding(1)
+var irradiance_texture: texture_cube<f32>;
+@group(2) @binding(2)
+var specular_texture: texture_cube<f32>;
+@group(2) @binding(3)
+var environment_sampler: sampler;
+
+// Karis' analytical fit of the split-sum BRDF term
+fn env_brdf_approx(f0: vec3<f32>, roughness: f32, n_dot_v: f32) -> vec3<f32> {
+    let c0 = vec4<f32>(-1.0, -0.0275, -0.572, 0.022);
+    let c1 = vec4<f32>(1.0, 0.0425, 1.04, -0.04);
+    let r = roughness * c0 + c1;
+    let a004 = min(r.x * r.x, exp2(-9.28 * n_dot_v)) * r.x + r.y;
+    let ab = vec2<f32>(-1.04, 1.04) * a004 + r.zw;
+    return f0 * ab.x + ab.y;
+}
+
+// Distance to the nearest edge of the triangle, in pixels
+fn edge_distance(barycentric: vec3<f32>) -> f32 {
+    let distances = barycentric / fwidth(barycentric);
+    return min(min(distances.x, distances.y), distances.z);
+}
+
+fn shade(in: VertexOutput) -> vec4<f32> {
+    let albedo = textureSample(color_texture, color_sampler, in.tex_coord, in.layer) * in.tint;
+
+    let incident = normalize(in.world_position - world.eye);
+    var normal = normalize(in.normal);
+    // The quads are visible from both sides
+    normal = select(normal, -normal, dot(normal, incident) > 0.0);
+
+    let irradiance = textureSample(irradiance_texture, environment_sampler, normal).rgb;
+    let diffuse = select(albedo.rgb, albedo.rgb * irradiance, world.ibl != 0u);
+
+    // Reflections like of a metal with the albedo color
+    let lod = world.roughness * f32(textureNumLevels(specular_texture) - 1u);
+    let prefiltered = textureSampleLevel(
+        specular_texture,
+        environment_sampler,
+        reflect(incident, normal),
+        lod,
+    ).rgb;
+    let n_dot_v = max(dot(normal, -incident), 0.0);
+    let specular = prefiltered * env_brdf_approx(albedo.rgb, world.roughness, n_dot_v);
+
+    let edge = edge_distance(in.barycentric);
+    if world.wireframe != 0u && edge > 1.0 {
+        discard;
+    }
+
+    return vec4<f32>(mix(diffuse, specular, world.reflectivity), albedo.a);
+}
+
 // Fragment shader
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(color_texture, color_sampler, in.tex_coord);
+    return shade(in);
+}
+
+@fragment
+fn fs_cutout(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = shade(in);
+    if color.a < world.alpha_cutoff {
+        discard;
+    }
+    return vec4<f32>(color.rgb, 1.0);
+}
+
+// Whether shade drops the fragment with the alpha cutoff or the wireframe,
+// for the passes not shading it
+fn discarded(in: VertexOutput) -> bool {
+    let alpha = textureSample(color_texture, color_sampler, in.tex_coord, in.layer).a * in.tint.a;
+    let edge = edge_distance(in.barycentric);
+    return alpha < world.alpha_cutoff || (world.wireframe != 0u && edge > 1.0);
+}
+
+// Depth pre-pass of fs_cutout, and of fs_main with the wireframe
+@fragment
+fn fs_depth_cutout(in: VertexOutput) {
+    if discarded(in) {
+        discard;
+    }
+}
+
+struct OitOutput {
+    @location(0) accum: vec4<f32>,
+    @location(1) revealage: f32,
+}
+
+// Weighted blended order-independent transparency
+@fragment
+fn fs_oit(in: VertexOutput) -> OitOutput {
+    let color = shade(in);
+
+    // Nearer fragments dominate the average (McGuire and Bavoil, equation 7)
+    let z = distance(in.world_position, world.eye);
+    let weight = color.a * clamp(
+        10.0 / (1e-5 + pow(z / 5.0, 2.0) + pow(z / 200.0, 6.0)),
+        1e-2,
+        3e3,
+    );
+
+    var out: OitOutput;
+    out.accum = vec4<f32>(color.rgb * color.a, color.a) * weight;
+    out.revealage = color.a;
+    return out;
+}
+
+struct IdOutput {
+    // Zero is left where nothing is drawn
+    @location(0) id: u32,
+    @location(1) depth: f32,
+}
+
+// Instance under each pixel, for picking; transparent texels are skipped
+// like in fs_depth_cutout
+@fragment
+fn fs_id(in: VertexOutput) -> IdOutput {
+    let alpha = textureSample(color_texture, color_sampler, in.tex_coord, in.layer).a * in.tint.a;
+    if alpha < world.alpha_cutoff {
+        discard;
+    }
+
+    var out: IdOutput;
+    out.id = in.instance + 1u;
+    out.depth = in.clip_position.z;
+    return out;
+}
+
+// Adds one per fragment, for the overdraw heat map
+@fragment
+fn fs_overdraw(in: VertexOutput) -> @location(0) vec4<f32> {
+    let edge = edge_distance(in.barycentric);
+    if world.wireframe != 0u && edge > 1.0 {
+        discard;
+    }
+    return vec4<f32>(1.0);
+}
+
+// fs_overdraw for the modes discarding with the alpha cutoff
+@fragment
+fn fs_overdraw_cutout(in: VertexOutput) -> @location(0) vec4<f32> {
+    if discarded(in) {
+        discard;
+    }
+    return vec4<f32>(1.0);
 }
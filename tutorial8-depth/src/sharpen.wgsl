@@ -0,0 +1,21 @@
+// Unsharp mask with the four direct neighbours
+
+struct SharpenParams {
+    strength: f32,
+}
+
+@group(1) @binding(0)
+var<uniform> params: SharpenParams;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let texel = texel_size();
+    let color = textureSample(input_texture, input_sampler, in.uv);
+    let neighbours =
+        textureSample(input_texture, input_sampler, in.uv + vec2<f32>(texel.x, 0.0)).rgb +
+        textureSample(input_texture, input_sampler, in.uv - vec2<f32>(texel.x, 0.0)).rgb +
+        textureSample(input_texture, input_sampler, in.uv + vec2<f32>(0.0, texel.y)).rgb +
+        textureSample(input_texture, input_sampler, in.uv - vec2<f32>(0.0, texel.y)).rgb;
+    let sharpened = color.rgb + (color.rgb * 4.0 - neighbours) * params.strength;
+    return vec4<f32>(max(sharpened, vec3<f32>(0.0)), color.a);
+}
@@ -0,0 +1,245 @@
+use std::path::Path;
+
+use glam::{Mat4, Vec3};
+use image::RgbaImage;
+
+use crate::ibl::Environment;
+
+/// Face images in the order of the cube texture layers: +X, -X, +Y, -Y, +Z, -Z.
+pub type CubeFaces = [RgbaImage; 6];
+
+/// File stems of the faces when loading them from a directory
+const FACE_NAMES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+
+/// Loads the faces from a directory of six images named `px`, `nx`, `py`,
+/// `ny`, `pz` and `nz`, or from a single image with a horizontal (4x3) or
+/// vertical (3x4) cross layout.
+pub fn load_faces(path: &Path) -> Result<CubeFaces, String> {
+    if path.is_dir() {
+        let faces = FACE_NAMES.map(|name| load_face(path, name));
+        let [px, nx, py, ny, pz, nz] = faces;
+        let faces = [px?, nx?, py?, ny?, pz?, nz?];
+        let size = faces[0].width();
+        if faces
+            .iter()
+            .any(|face| face.width() != size || face.height() != size)
+        {
+            return Err("the faces have to be square and of the same size".to_string());
+        }
+        Ok(faces)
+    } else {
+        let image = image::open(path).map_err(|err| err.to_string())?.to_rgba8();
+        split_cross(&image)
+    }
+}
+
+fn load_face(dir: &Path, name: &str) -> Result<RgbaImage, String> {
+    ["png", "jpg", "jpeg"]
+        .iter()
+        .map(|extension| dir.join(name).with_extension(extension))
+        .find(|path| path.exists())
+        .ok_or_else(|| format!("missing face '{}' in {}", name, dir.display()))
+        .and_then(|path| match image::open(&path) {
+            Ok(image) => Ok(image.to_rgba8()),
+            Err(err) => Err(format!("{}: {}", path.display(), err)),
+        })
+}
+
+/// Cuts the faces out of a cross layout.
+fn split_cross(image: &RgbaImage) -> Result<CubeFaces, String> {
+    let (width, height) = image.dimensions();
+    let face = |column: u32, row: u32, size: u32| {
+        image::imageops::crop_imm(image, column * size, row * size, size, size).to_image()
+    };
+
+    if width * 3 == height * 4 {
+        //    +Y
+        // -X +Z +X -Z
+        //    -Y
+        let size = width / 4;
+        Ok([
+            face(2, 1, size),
+            face(0, 1, size),
+            face(1, 0, size),
+            face(1, 2, size),
+            face(1, 1, size),
+            face(3, 1, size),
+        ])
+    } else if width * 4 == height * 3 {
+        //    +Y
+        // -X +Z +X
+        //    -Y
+        //    -Z (upside down)
+        let size = width / 3;
+        Ok([
+            face(2, 1, size),
+            face(0, 1, size),
+            face(1, 0, size),
+            face(1, 2, size),
+            face(1, 1, size),
+            image::imageops::rotate180(&face(1, 3, size)),
+        ])
+    } else {
+        Err(format!(
+            "{}x{} is neither a 4x3 nor a 3x4 cross layout",
+            width, height
+        ))
+    }
+}
+
+/// Direction through the texel at `s`, `t` (in `-1..=1`, `t` pointing down) of a face.
+fn face_direction(face: usize, s: f32, t: f32) -> Vec3 {
+    match face {
+        0 => Vec3::new(1.0, -t, -s),
+        1 => Vec3::new(-1.0, -t, s),
+        2 => Vec3::new(s, 1.0, t),
+        3 => Vec3::new(s, -1.0, -t),
+        4 => Vec3::new(s, -t, 1.0),
+        _ => Vec3::new(-s, -t, -1.0),
+    }
+    .normalize()
+}
+
+/// A simple sky fading from the horizon to the zenith over a dark ground,
+/// used when no images are given.
+pub fn gradient_faces(size: u32) -> CubeFaces {
+    let zenith = Vec3::new(0.1, 0.3, 0.7);
+    let horizon = Vec3::new(0.7, 0.8, 0.9);
+    let ground = Vec3::new(0.15, 0.13, 0.12);
+
+    std::array::from_fn(|face| {
+        RgbaImage::from_fn(size, size, |x, y| {
+            let s = (x as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+            let t = (y as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+            let up = face_direction(face, s, t).y;
+            let color = if up >= 0.0 {
+                horizon.lerp(zenith, up.powf(0.5))
+            } else {
+                horizon.lerp(ground, (-up * 4.0).min(1.0))
+            };
+            let [r, g, b] = color.to_array().map(|c| (c * 255.0).round() as u8);
+            image::Rgba([r, g, b, 255])
+        })
+    })
+}
+
+/// Draws the environment cube map behind the scene.
+pub struct Skybox {
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+}
+
+impl Skybox {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
+        environment: &Environment,
+    ) -> Self {
+        let uniform_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("skybox"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("skybox uniforms"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            size: std::mem::size_of::<Mat4>() as u64,
+            mapped_at_creation: false,
+        });
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("skybox uniforms"),
+            layout: &uniform_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        let pipeline = {
+            let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("skybox"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("skybox.wgsl").into()),
+            });
+
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("skybox"),
+                bind_group_layouts: &[&uniform_layout, environment.layout()],
+                push_constant_ranges: &[],
+            });
+
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("skybox"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader_module,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader_module,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                // Only where nothing was drawn, the far plane is at depth 1
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: depth_format,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::LessEqual,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+            })
+        };
+
+        Self {
+            pipeline,
+            uniform_buffer,
+            uniform_bind_group,
+        }
+    }
+
+    pub fn prepare(&self, queue: &wgpu::Queue, view: Mat4, projection: Mat4) {
+        // Only the rotation of the camera matters for the sky
+        let rotation = Mat4::from_mat3(glam::Mat3::from_mat4(view));
+        let inv_view_proj = (projection * rotation).inverse();
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&inv_view_proj.to_cols_array()),
+        );
+    }
+
+    /// Draws the sky into a pass with the depth of the scene; call after the
+    /// opaque geometry.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        environment: &'a Environment,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+        render_pass.set_bind_group(1, environment.bind_group(), &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
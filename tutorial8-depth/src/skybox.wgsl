@@ -0,0 +1,35 @@
+// Cube map drawn at the far plane behind the scene
+
+struct SkyUniform {
+    // Inverse of the view-projection without the camera translation
+    inv_view_proj: mat4x4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> sky: SkyUniform;
+
+@group(1) @binding(0)
+var environment_texture: texture_cube<f32>;
+@group(1) @binding(3)
+var environment_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) direction: vec3<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    // A full-screen triangle at depth 1
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 1.0, 1.0);
+    let world = sky.inv_view_proj * out.clip_position;
+    out.direction = world.xyz / world.w;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(environment_texture, environment_sampler, in.direction);
+}
@@ -0,0 +1,296 @@
+use glam::Vec3;
+use wgpu::util::DeviceExt;
+
+use crate::InstanceRaw;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SortGlobals {
+    eye: [f32; 3],
+    count: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SortStage {
+    k: u32,
+    j: u32,
+}
+
+/// Orders the instances from the farthest to the nearest to `eye`, returning
+/// their indices.
+pub fn back_to_front(raws: &[InstanceRaw], eye: Vec3) -> Vec<u32> {
+    let mut keys: Vec<(f32, u32)> = raws
+        .iter()
+        .enumerate()
+        .map(|(i, raw)| {
+            let position = Vec3::from_slice(&raw.matrix[12..15]);
+            (position.distance_squared(eye), i as u32)
+        })
+        .collect();
+    keys.sort_unstable_by(|a, b| b.0.total_cmp(&a.0));
+    keys.iter().map(|&(_, i)| i).collect()
+}
+
+/// Back-to-front sorting of the instances with a bitonic sort in compute shaders.
+///
+/// The unsorted instances are written to [`GpuSort::input`]; after
+/// [`GpuSort::encode`], [`GpuSort::output`] holds them sorted and can be bound
+/// as the instance buffer.
+pub struct GpuSort {
+    layout: wgpu::BindGroupLayout,
+    keys_pipeline: wgpu::ComputePipeline,
+    sort_pipeline: wgpu::ComputePipeline,
+    gather_pipeline: wgpu::ComputePipeline,
+    globals_buffer: wgpu::Buffer,
+    buffers: SortBuffers,
+}
+
+/// Buffers depending on the number of instances.
+struct SortBuffers {
+    input: wgpu::Buffer,
+    output: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    /// One `SortStage` per merge step, at `stage_stride` apart
+    stage_count: u32,
+    stage_stride: u32,
+    count: u32,
+    padded_count: u32,
+}
+
+impl GpuSort {
+    const WORKGROUP_SIZE: u32 = 256;
+
+    pub fn is_supported(adapter: &wgpu::Adapter) -> bool {
+        adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+    }
+
+    pub fn new(device: &wgpu::Device, count: usize) -> Self {
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("gpu sort"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(2, true),
+                storage_entry(3, false),
+                storage_entry(4, false),
+            ],
+        });
+
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("gpu sort"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("sort.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("gpu sort"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |label, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &shader_module,
+                entry_point,
+            })
+        };
+
+        let globals_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("gpu sort globals"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            size: std::mem::size_of::<SortGlobals>() as u64,
+            mapped_at_creation: false,
+        });
+
+        let buffers = SortBuffers::new(device, &layout, &globals_buffer, count);
+
+        Self {
+            keys_pipeline: pipeline("gpu sort keys", "cs_keys"),
+            sort_pipeline: pipeline("gpu sort", "cs_sort"),
+            gather_pipeline: pipeline("gpu sort gather", "cs_gather"),
+            layout,
+            globals_buffer,
+            buffers,
+        }
+    }
+
+    /// Recreates the buffers for a new number of instances.
+    pub fn resize(&mut self, device: &wgpu::Device, count: usize) {
+        self.buffers = SortBuffers::new(device, &self.layout, &self.globals_buffer, count);
+    }
+
+    /// Buffer the unsorted instances are written to.
+    pub fn input(&self) -> &wgpu::Buffer {
+        &self.buffers.input
+    }
+
+    /// Buffer holding the sorted instances.
+    pub fn output(&self) -> &wgpu::Buffer {
+        &self.buffers.output
+    }
+
+    pub fn prepare(&self, queue: &wgpu::Queue, eye: Vec3) {
+        queue.write_buffer(
+            &self.globals_buffer,
+            0,
+            bytemuck::bytes_of(&SortGlobals {
+                eye: eye.to_array(),
+                count: self.buffers.count,
+            }),
+        );
+    }
+
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder) {
+        let buffers = &self.buffers;
+        let workgroups = |n: u32| n.div_ceil(Self::WORKGROUP_SIZE);
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("gpu sort"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.keys_pipeline);
+        compute_pass.set_bind_group(0, &buffers.bind_group, &[0]);
+        compute_pass.dispatch_workgroups(workgroups(buffers.padded_count), 1, 1);
+
+        compute_pass.set_pipeline(&self.sort_pipeline);
+        for stage in 0..buffers.stage_count {
+            compute_pass.set_bind_group(0, &buffers.bind_group, &[stage * buffers.stage_stride]);
+            compute_pass.dispatch_workgroups(workgroups(buffers.padded_count), 1, 1);
+        }
+
+        compute_pass.set_pipeline(&self.gather_pipeline);
+        compute_pass.set_bind_group(0, &buffers.bind_group, &[0]);
+        compute_pass.dispatch_workgroups(workgroups(buffers.count), 1, 1);
+    }
+}
+
+impl SortBuffers {
+    fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        globals_buffer: &wgpu::Buffer,
+        count: usize,
+    ) -> Self {
+        // Bitonic sorting works on powers of two
+        let padded_count = (count as u32).next_power_of_two().max(2);
+
+        let instances_size = (std::mem::size_of::<InstanceRaw>() * count.max(1)) as u64;
+        // Also drawn from where the order does not matter
+        let input = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("gpu sort input"),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::VERTEX,
+            size: instances_size,
+            mapped_at_creation: false,
+        });
+        let output = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("gpu sort output"),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            size: instances_size,
+            mapped_at_creation: false,
+        });
+        let pairs = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("gpu sort pairs"),
+            usage: wgpu::BufferUsages::STORAGE,
+            size: padded_count as u64 * 8,
+            mapped_at_creation: false,
+        });
+
+        let stage_stride = device
+            .limits()
+            .min_uniform_buffer_offset_alignment
+            .max(std::mem::size_of::<SortStage>() as u32);
+        let mut stages = Vec::new();
+        let mut k = 2;
+        while k <= padded_count {
+            let mut j = k / 2;
+            while j > 0 {
+                let mut bytes = vec![0u8; stage_stride as usize];
+                bytes[..std::mem::size_of::<SortStage>()]
+                    .copy_from_slice(bytemuck::bytes_of(&SortStage { k, j }));
+                stages.extend(bytes);
+                j /= 2;
+            }
+            k *= 2;
+        }
+        let stage_count = stages.len() as u32 / stage_stride;
+        let stages_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("gpu sort stages"),
+            usage: wgpu::BufferUsages::UNIFORM,
+            contents: &stages,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("gpu sort"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: globals_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &stages_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(std::mem::size_of::<SortStage>() as u64),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: input.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: pairs.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: output.as_entire_binding(),
+                },
+            ],
+        });
+
+        Self {
+            input,
+            output,
+            bind_group,
+            stage_count,
+            stage_stride,
+            count: count as u32,
+            padded_count,
+        }
+    }
+}
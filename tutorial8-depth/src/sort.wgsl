@@ -0,0 +1,79 @@
+// Bitonic sort of the instances from back to front
+
+struct SortGlobals {
+    eye: vec3<f32>,
+    count: u32,
+}
+
+struct SortStage {
+    // Size of the bitonic sequences being merged
+    k: u32,
+    // Distance of the compared elements
+    j: u32,
+}
+
+// Mirrors InstanceRaw in lib.rs
+struct InstanceRaw {
+    matrix: mat4x4<f32>,
+    tint: vec4<f32>,
+    layer: u32,
+    scale: f32,
+    value: f32,
+    flags: u32,
+}
+
+struct SortPair {
+    key: f32,
+    index: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> globals: SortGlobals;
+@group(0) @binding(1)
+var<uniform> stage: SortStage;
+@group(0) @binding(2)
+var<storage, read> instances: array<InstanceRaw>;
+@group(0) @binding(3)
+var<storage, read_write> pairs: array<SortPair>;
+@group(0) @binding(4)
+var<storage, read_write> sorted: array<InstanceRaw>;
+
+// Keys sort ascending; padding goes to the end
+@compute @workgroup_size(256)
+fn cs_keys(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if i >= arrayLength(&pairs) {
+        return;
+    }
+    if i < globals.count {
+        let offset = instances[i].matrix[3].xyz - globals.eye;
+        pairs[i] = SortPair(-dot(offset, offset), i);
+    } else {
+        pairs[i] = SortPair(3.4e38, i);
+    }
+}
+
+@compute @workgroup_size(256)
+fn cs_sort(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    let l = i ^ stage.j;
+    if i >= arrayLength(&pairs) || l <= i {
+        return;
+    }
+    let a = pairs[i];
+    let b = pairs[l];
+    let ascending = (i & stage.k) == 0u;
+    if (a.key > b.key) == ascending {
+        pairs[i] = b;
+        pairs[l] = a;
+    }
+}
+
+@compute @workgroup_size(256)
+fn cs_gather(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if i >= globals.count {
+        return;
+    }
+    sorted[i] = instances[pairs[i].index];
+}
@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+
+use ab_glyph::{Font, FontRef, PxScale, ScaleFont};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TextVertex {
+    position: [f32; 2],
+    tex_coord: [f32; 2],
+    color: [f32; 4],
+}
+
+impl TextVertex {
+    fn layout() -> wgpu::VertexBufferLayout<'static> {
+        const ATRIBUTES: [wgpu::VertexAttribute; 3] =
+            wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Float32x4];
+
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<TextVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATRIBUTES,
+        }
+    }
+}
+
+/// Placement of a rasterized glyph in the atlas.
+#[derive(Copy, Clone, Debug)]
+struct GlyphInfo {
+    /// Top-left corner in the atlas, in texels
+    origin: [u32; 2],
+    size: [u32; 2],
+    /// Offset of the bitmap from the pen position on the baseline
+    offset: [f32; 2],
+}
+
+/// Glyph coverage of the printable ASCII characters rasterized into a
+/// single-channel texture.
+struct GlyphAtlas {
+    glyphs: HashMap<char, GlyphInfo>,
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl GlyphAtlas {
+    const WIDTH: u32 = 512;
+    const PADDING: u32 = 1;
+
+    fn new(font: &FontRef, scale: PxScale) -> Self {
+        let mut glyphs = HashMap::new();
+        let mut outlines = Vec::new();
+
+        // Pack glyphs in rows from left to right
+        let (mut x, mut y, mut row_height) = (Self::PADDING, Self::PADDING, 0);
+        for c in (0x20u8..0x7f).map(char::from) {
+            let glyph = font
+                .glyph_id(c)
+                .with_scale_and_position(scale, ab_glyph::point(0.0, 0.0));
+            let Some(outline) = font.outline_glyph(glyph) else {
+                continue;
+            };
+            let bounds = outline.px_bounds();
+            let (w, h) = (bounds.width() as u32, bounds.height() as u32);
+            if x + w + Self::PADDING > Self::WIDTH {
+                x = Self::PADDING;
+                y += row_height + Self::PADDING;
+                row_height = 0;
+            }
+            glyphs.insert(
+                c,
+                GlyphInfo {
+                    origin: [x, y],
+                    size: [w, h],
+                    offset: [bounds.min.x, bounds.min.y],
+                },
+            );
+            outlines.push(([x, y], outline));
+            x += w + Self::PADDING;
+            row_height = row_height.max(h);
+        }
+
+        let width = Self::WIDTH;
+        let height = (y + row_height + Self::PADDING).next_power_of_two();
+        let mut pixels = vec![0u8; (width * height) as usize];
+        for ([ox, oy], outline) in outlines {
+            outline.draw(|x, y, coverage| {
+                let i = (oy + y) * width + ox + x;
+                pixels[i as usize] = (coverage.clamp(0.0, 1.0) * 255.0) as u8;
+            });
+        }
+
+        Self {
+            glyphs,
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+/// Batches text into textured quads and draws them over the scene.
+///
+/// Text is queued with [`TextRenderer::queue`] during the frame, uploaded
+/// (and cleared) by [`TextRenderer::prepare`] and drawn by
+/// [`TextRenderer::render`].
+pub struct TextRenderer {
+    font: FontRef<'static>,
+    scale: PxScale,
+    atlas: GlyphAtlas,
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    vertex_buffer: wgpu::Buffer,
+    vertices: Vec<TextVertex>,
+    /// Vertices uploaded by the last `prepare`
+    vertex_count: u32,
+}
+
+impl TextRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color_format: wgpu::TextureFormat,
+        font_size: f32,
+    ) -> Self {
+        let font = FontRef::try_from_slice(include_bytes!("DejaVuSansMono.ttf"))
+            .expect("Font should be loaded");
+        let scale = PxScale::from(font_size);
+        let atlas = GlyphAtlas::new(&font, scale);
+
+        let size = wgpu::Extent3d {
+            width: atlas.width,
+            height: atlas.height,
+            depth_or_array_layers: 1,
+        };
+        let atlas_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("glyph atlas"),
+            size,
+            dimension: wgpu::TextureDimension::D2,
+            mip_level_count: 1,
+            sample_count: 1,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &atlas_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &atlas.pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(atlas.width),
+                rows_per_image: Some(atlas.height),
+            },
+            size,
+        );
+        let atlas_view = atlas_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // Quads are placed on whole pixels, so no filtering is needed
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("glyph atlas"),
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("text"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("text"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&atlas_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        let pipeline = {
+            let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("text"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("text.wgsl").into()),
+            });
+
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("text"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("text"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader_module,
+                    entry_point: "vs_main",
+                    buffers: &[TextVertex::layout()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader_module,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    cull_mode: None,
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+
+        Self {
+            font,
+            scale,
+            atlas,
+            pipeline,
+            bind_group,
+            // Room for 1024 glyphs
+            vertex_buffer: Self::create_vertex_buffer(device, 6 * 1024),
+            vertices: Vec::new(),
+            vertex_count: 0,
+        }
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, num_vertices: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("text vertices"),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            size: (std::mem::size_of::<TextVertex>() * num_vertices) as u64,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Height of a line of text in pixels.
+    pub fn line_height(&self) -> f32 {
+        let scaled = self.font.as_scaled(self.scale);
+        scaled.height() + scaled.line_gap()
+    }
+
+    /// Queues `text` with its top-left corner at `position` (in pixels from
+    /// the top-left of the screen). `color` is in linear RGBA.
+    pub fn queue(&mut self, text: &str, position: [f32; 2], color: [f32; 4]) {
+        let scaled = self.font.as_scaled(self.scale);
+        let [left, top] = position.map(f32::round);
+        let mut pen = [left, (top + scaled.ascent()).round()];
+        let mut previous = None;
+
+        for c in text.chars() {
+            if c == '\n' {
+                pen = [left, (pen[1] + self.line_height()).round()];
+                previous = None;
+                continue;
+            }
+            let id = scaled.glyph_id(c);
+            if let Some(previous) = previous {
+                pen[0] += scaled.kern(previous, id);
+            }
+            previous = Some(id);
+
+            if let Some(glyph) = self.atlas.glyphs.get(&c) {
+                let x0 = (pen[0] + glyph.offset[0]).round();
+                let y0 = pen[1] + glyph.offset[1];
+                let [x1, y1] = [x0 + glyph.size[0] as f32, y0 + glyph.size[1] as f32];
+                let [w, h] = [self.atlas.width as f32, self.atlas.height as f32];
+                let u0 = glyph.origin[0] as f32 / w;
+                let v0 = glyph.origin[1] as f32 / h;
+                let u1 = (glyph.origin[0] + glyph.size[0]) as f32 / w;
+                let v1 = (glyph.origin[1] + glyph.size[1]) as f32 / h;
+
+                let vertex = |x, y, u, v| TextVertex {
+                    position: [x, y],
+                    tex_coord: [u, v],
+                    color,
+                };
+                self.vertices.extend_from_slice(&[
+                    vertex(x0, y0, u0, v0),
+                    vertex(x0, y1, u0, v1),
+                    vertex(x1, y1, u1, v1),
+                    vertex(x0, y0, u0, v0),
+                    vertex(x1, y1, u1, v1),
+                    vertex(x1, y0, u1, v0),
+                ]);
+            }
+            pen[0] += scaled.h_advance(id);
+        }
+    }
+
+    /// Uploads all queued text and clears the queue.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, screen_size: [u32; 2]) {
+        self.vertex_count = self.vertices.len() as u32;
+        if self.vertices.is_empty() {
+            return;
+        }
+
+        // Convert pixel coordinates into the clip space
+        let [w, h] = screen_size.map(|v| v.max(1) as f32);
+        for vertex in &mut self.vertices {
+            let [x, y] = vertex.position;
+            vertex.position = [x / w * 2.0 - 1.0, 1.0 - y / h * 2.0];
+        }
+
+        let size = std::mem::size_of_val(self.vertices.as_slice()) as u64;
+        if size > self.vertex_buffer.size() {
+            self.vertex_buffer =
+                Self::create_vertex_buffer(device, self.vertices.len().next_power_of_two());
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.vertices));
+        self.vertices.clear();
+    }
+
+    /// Draws the text uploaded by `prepare` onto `view`.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        if self.vertex_count == 0 {
+            return;
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("text"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        let size = (std::mem::size_of::<TextVertex>() * self.vertex_count as usize) as u64;
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..size));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
@@ -0,0 +1,39 @@
+// Maps the HDR scene to the displayable range
+
+struct TonemapParams {
+    exposure: f32,
+    mode: u32,
+}
+
+@group(1) @binding(0)
+var<uniform> params: TonemapParams;
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let hdr = textureSample(input_texture, input_sampler, in.uv);
+    let color = hdr.rgb * params.exposure;
+
+    var mapped: vec3<f32>;
+    switch params.mode {
+        case 1u: {
+            mapped = color / (color + 1.0);
+        }
+        case 2u: {
+            mapped = aces(color);
+        }
+        default: {
+            mapped = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+    }
+    return vec4<f32>(mapped, hdr.a);
+}
@@ -0,0 +1,432 @@
+use winit::window::Window;
+
+use crate::settings::{
+    DepthPrepass, InstanceStyle, Material, Picking, PostProcessing, Rasterization, Settings,
+    Tonemapping, Transparency,
+};
+
+/// Immediate-mode GUI for tweaking the scene at runtime.
+pub struct DebugUi {
+    pub visible: bool,
+    context: egui::Context,
+    winit_state: egui_winit::State,
+    renderer: egui_wgpu::Renderer,
+    paint_jobs: Vec<egui::ClippedPrimitive>,
+    textures_delta: egui::TexturesDelta,
+    pixels_per_point: f32,
+    /// Screen size of the last `prepare`
+    screen_size: [u32; 2],
+}
+
+impl DebugUi {
+    pub fn new(device: &wgpu::Device, window: &Window, color_format: wgpu::TextureFormat) -> Self {
+        let context = egui::Context::default();
+        let winit_state = egui_winit::State::new(
+            context.clone(),
+            egui::ViewportId::ROOT,
+            window,
+            Some(window.scale_factor() as f32),
+            Some(device.limits().max_texture_dimension_2d as usize),
+        );
+        let renderer = egui_wgpu::Renderer::new(device, color_format, None, 1);
+
+        Self {
+            visible: false,
+            context,
+            winit_state,
+            renderer,
+            paint_jobs: Vec::new(),
+            textures_delta: egui::TexturesDelta::default(),
+            pixels_per_point: window.scale_factor() as f32,
+            screen_size: [0, 0],
+        }
+    }
+
+    /// Returns `true` if the event was consumed by the GUI.
+    pub fn on_window_event(&mut self, window: &Window, event: &winit::event::WindowEvent) -> bool {
+        if !self.visible {
+            return false;
+        }
+        self.winit_state.on_window_event(window, event).consumed
+    }
+
+    /// Lays out the GUI for this frame, letting it modify `settings` and the
+    /// camera distance.
+    pub fn run(&mut self, window: &Window, settings: &mut Settings, distance: &mut f32) {
+        if !self.visible {
+            return;
+        }
+
+        let raw_input = self.winit_state.take_egui_input(window);
+        let output = self.context.run(raw_input, |ctx| {
+            egui::Window::new("Settings").show(ctx, |ui| {
+                settings_ui(ui, settings, distance);
+            });
+        });
+        self.winit_state
+            .handle_platform_output(window, output.platform_output);
+
+        self.paint_jobs = self
+            .context
+            .tessellate(output.shapes, output.pixels_per_point);
+        self.textures_delta.append(output.textures_delta);
+        self.pixels_per_point = output.pixels_per_point;
+    }
+
+    /// Uploads the textures and buffers of the GUI laid out by the last `run`.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, screen_size: [u32; 2]) {
+        for (id, image_delta) in &self.textures_delta.set {
+            self.renderer
+                .update_texture(device, queue, *id, image_delta);
+        }
+        for id in &self.textures_delta.free {
+            self.renderer.free_texture(id);
+        }
+        self.textures_delta.clear();
+
+        if !self.visible || self.paint_jobs.is_empty() {
+            return;
+        }
+
+        self.screen_size = screen_size;
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("ui") });
+        let command_buffers = self.renderer.update_buffers(
+            device,
+            queue,
+            &mut encoder,
+            &self.paint_jobs,
+            &self.screen_descriptor(),
+        );
+        queue.submit(command_buffers.into_iter().chain([encoder.finish()]));
+    }
+
+    fn screen_descriptor(&self) -> egui_wgpu::ScreenDescriptor {
+        egui_wgpu::ScreenDescriptor {
+            size_in_pixels: self.screen_size,
+            pixels_per_point: self.pixels_per_point,
+        }
+    }
+
+    /// Draws the GUI uploaded by `prepare` onto `view`.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        if !self.visible || self.paint_jobs.is_empty() {
+            return;
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("ui"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        self.renderer.render(
+            &mut render_pass,
+            &self.paint_jobs,
+            &self.screen_descriptor(),
+        );
+    }
+}
+
+fn settings_ui(ui: &mut egui::Ui, settings: &mut Settings, distance: &mut f32) {
+    egui::Grid::new("settings").num_columns(2).show(ui, |ui| {
+        ui.label("Grid size");
+        ui.add(egui::Slider::new(&mut settings.grid_size, 0..=400));
+        ui.end_row();
+
+        ui.label("Camera distance");
+        ui.add(egui::Slider::new(distance, 0.1..=500.0).logarithmic(true));
+        ui.end_row();
+
+        ui.label("Clear color");
+        ui.add_enabled_ui(!settings.skybox, |ui| {
+            ui.color_edit_button_rgb(&mut settings.clear_color)
+        });
+        ui.end_row();
+
+        ui.label("Skybox");
+        ui.checkbox(&mut settings.skybox, "");
+        ui.end_row();
+
+        ui.label("Reflectivity");
+        ui.add(egui::Slider::new(&mut settings.reflectivity, 0.0..=1.0));
+        ui.end_row();
+
+        ui.label("Roughness");
+        ui.add(egui::Slider::new(&mut settings.roughness, 0.0..=1.0));
+        ui.end_row();
+
+        ui.label("Image-based lighting");
+        ui.checkbox(&mut settings.ibl, "");
+        ui.end_row();
+
+        ui.label("GPU sorting");
+        ui.add_enabled(
+            settings.material.transparency == Transparency::Sorted,
+            egui::Checkbox::new(&mut settings.gpu_sort, ""),
+        );
+        ui.end_row();
+
+        ui.label("Depth pre-pass");
+        egui::ComboBox::from_id_source("depth_prepass")
+            .selected_text(format!("{:?}", settings.depth_prepass))
+            .show_ui(ui, |ui| {
+                let depth_prepass = &mut settings.depth_prepass;
+                ui.selectable_value(depth_prepass, DepthPrepass::Off, "Off");
+                ui.selectable_value(depth_prepass, DepthPrepass::Equal, "Equal");
+                ui.selectable_value(depth_prepass, DepthPrepass::LessEqual, "Less or equal");
+            });
+        ui.end_row();
+
+        ui.label("Picking");
+        egui::ComboBox::from_id_source("picking")
+            .selected_text(format!("{:?}", settings.picking))
+            .show_ui(ui, |ui| {
+                let picking = &mut settings.picking;
+                ui.selectable_value(picking, Picking::RayCast, "Ray cast");
+                ui.selectable_value(picking, Picking::IdBuffer, "ID buffer");
+            });
+        ui.end_row();
+
+        ui.label("Overdraw");
+        ui.checkbox(&mut settings.overdraw, "");
+        ui.end_row();
+
+        ui.label("Overdraw range");
+        ui.add_enabled(
+            settings.overdraw,
+            egui::Slider::new(&mut settings.overdraw_max_count, 1.0..=256.0)
+                .logarithmic(true)
+                .suffix(" fragments"),
+        );
+        ui.end_row();
+    });
+
+    ui.collapsing("Material", |ui| {
+        material_ui(ui, &mut settings.material);
+    });
+
+    ui.collapsing("Instances", |ui| {
+        instance_style_ui(ui, &mut settings.instance_style);
+    });
+
+    ui.collapsing("Rasterization", |ui| {
+        rasterization_ui(ui, &mut settings.rasterization);
+    });
+
+    ui.collapsing("Wave", |ui| {
+        let wave = &mut settings.wave;
+        egui::Grid::new("wave").num_columns(2).show(ui, |ui| {
+            for (label, value, range) in [
+                ("Ripple speed", &mut wave.ripple_speed, 0.0..=50.0),
+                ("Ripple amplitude", &mut wave.ripple_amplitude, 0.0..=1.0),
+                ("Swell radius", &mut wave.swell_radius, 1.0..=500.0),
+                ("Swell exponent", &mut wave.swell_exponent, 0.0..=5.0),
+                ("Swell speed", &mut wave.swell_speed, 0.0..=10.0),
+                ("Swell amplitude", &mut wave.swell_amplitude, 0.0..=50.0),
+            ] {
+                ui.label(label);
+                ui.add(egui::Slider::new(value, range));
+                ui.end_row();
+            }
+        });
+    });
+
+    ui.collapsing("Sampler", |ui| {
+        let filters = &mut settings.sampler_filters;
+        egui::Grid::new("sampler").num_columns(2).show(ui, |ui| {
+            for (label, filter) in [
+                ("Mag filter", &mut filters.mag_filter),
+                ("Min filter", &mut filters.min_filter),
+                ("Mipmap filter", &mut filters.mipmap_filter),
+            ] {
+                ui.label(label);
+                egui::ComboBox::from_id_source(label)
+                    .selected_text(format!("{:?}", filter))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(filter, wgpu::FilterMode::Nearest, "Nearest");
+                        ui.selectable_value(filter, wgpu::FilterMode::Linear, "Linear");
+                    });
+                ui.end_row();
+            }
+        });
+    });
+
+    ui.collapsing("Tonemapping", |ui| {
+        egui::Grid::new("tonemapping")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Operator");
+                egui::ComboBox::from_id_source("operator")
+                    .selected_text(format!("{:?}", settings.tonemapping))
+                    .show_ui(ui, |ui| {
+                        let tonemapping = &mut settings.tonemapping;
+                        ui.selectable_value(tonemapping, Tonemapping::Linear, "Linear");
+                        ui.selectable_value(tonemapping, Tonemapping::Reinhard, "Reinhard");
+                        ui.selectable_value(tonemapping, Tonemapping::Aces, "Aces");
+                    });
+                ui.end_row();
+
+                ui.label("Exposure");
+                ui.add(egui::Slider::new(&mut settings.exposure, -8.0..=8.0).suffix(" EV"));
+                ui.end_row();
+            });
+    });
+
+    ui.collapsing("Post-processing", |ui| {
+        post_processing_ui(ui, &mut settings.post_processing);
+    });
+
+    if ui.button("Reset").clicked() {
+        *settings = Settings::default();
+    }
+}
+
+fn material_ui(ui: &mut egui::Ui, material: &mut Material) {
+    egui::Grid::new("material").num_columns(2).show(ui, |ui| {
+        ui.label("Transparency");
+        egui::ComboBox::from_id_source("transparency")
+            .selected_text(format!("{:?}", material.transparency))
+            .show_ui(ui, |ui| {
+                let transparency = &mut material.transparency;
+                ui.selectable_value(transparency, Transparency::Opaque, "Opaque");
+                ui.selectable_value(transparency, Transparency::Cutout, "Cutout");
+                ui.selectable_value(
+                    transparency,
+                    Transparency::AlphaToCoverage,
+                    "Alpha to coverage",
+                );
+                ui.selectable_value(transparency, Transparency::Sorted, "Sorted");
+                ui.selectable_value(
+                    transparency,
+                    Transparency::WeightedBlended,
+                    "Weighted blended",
+                );
+            });
+        ui.end_row();
+
+        ui.label("Alpha cutoff");
+        ui.add_enabled(
+            material.transparency.uses_alpha_cutoff(),
+            egui::Slider::new(&mut material.alpha_cutoff, 0.0..=1.0),
+        );
+        ui.end_row();
+    });
+}
+
+fn instance_style_ui(ui: &mut egui::Ui, style: &mut InstanceStyle) {
+    egui::Grid::new("instances").num_columns(2).show(ui, |ui| {
+        ui.label("Tint");
+        ui.color_edit_button_rgb(&mut style.tint);
+        ui.end_row();
+
+        ui.label("Scale");
+        ui.add(egui::Slider::new(&mut style.scale, 0.1..=4.0));
+        ui.end_row();
+
+        ui.checkbox(&mut style.color_by_height, "Color by height");
+        ui.end_row();
+        ui.label("Height range");
+        ui.add_enabled(
+            style.color_by_height,
+            egui::Slider::new(&mut style.height_range, 0.1..=100.0).logarithmic(true),
+        );
+        ui.end_row();
+    });
+}
+
+fn rasterization_ui(ui: &mut egui::Ui, rasterization: &mut Rasterization) {
+    egui::Grid::new("rasterization")
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.checkbox(&mut rasterization.wireframe, "Wireframe");
+            ui.end_row();
+
+            ui.label("Cull mode");
+            egui::ComboBox::from_id_source("cull_mode")
+                .selected_text(match rasterization.cull_mode {
+                    Some(face) => format!("{:?}", face),
+                    None => "None".to_string(),
+                })
+                .show_ui(ui, |ui| {
+                    let cull_mode = &mut rasterization.cull_mode;
+                    ui.selectable_value(cull_mode, None, "None");
+                    ui.selectable_value(cull_mode, Some(wgpu::Face::Back), "Back");
+                    ui.selectable_value(cull_mode, Some(wgpu::Face::Front), "Front");
+                });
+            ui.end_row();
+
+            ui.label("Front face");
+            egui::ComboBox::from_id_source("front_face")
+                .selected_text(format!("{:?}", rasterization.front_face))
+                .show_ui(ui, |ui| {
+                    let front_face = &mut rasterization.front_face;
+                    ui.selectable_value(front_face, wgpu::FrontFace::Ccw, "Counter-clockwise");
+                    ui.selectable_value(front_face, wgpu::FrontFace::Cw, "Clockwise");
+                });
+            ui.end_row();
+        });
+}
+
+fn post_processing_ui(ui: &mut egui::Ui, post: &mut PostProcessing) {
+    egui::Grid::new("post_processing")
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.checkbox(&mut post.bloom, "Bloom");
+            ui.end_row();
+            ui.label("Threshold");
+            ui.add_enabled(
+                post.bloom,
+                egui::Slider::new(&mut post.bloom_threshold, 0.0..=4.0),
+            );
+            ui.end_row();
+            ui.label("Intensity");
+            ui.add_enabled(
+                post.bloom,
+                egui::Slider::new(&mut post.bloom_intensity, 0.0..=4.0),
+            );
+            ui.end_row();
+
+            ui.checkbox(&mut post.color_grading, "Color grading");
+            ui.end_row();
+            let grading = &mut post.grading;
+            for (label, value, range) in [
+                ("Contrast", &mut grading.contrast, 0.0..=2.0),
+                ("Saturation", &mut grading.saturation, 0.0..=2.0),
+                ("Temperature", &mut grading.temperature, -1.0..=1.0),
+            ] {
+                ui.label(label);
+                ui.add_enabled(post.color_grading, egui::Slider::new(value, range));
+                ui.end_row();
+            }
+
+            ui.checkbox(&mut post.sharpen, "Sharpen");
+            ui.end_row();
+            ui.label("Strength");
+            ui.add_enabled(
+                post.sharpen,
+                egui::Slider::new(&mut post.sharpen_strength, 0.0..=2.0),
+            );
+            ui.end_row();
+
+            ui.checkbox(&mut post.fxaa, "FXAA");
+            ui.end_row();
+
+            ui.checkbox(&mut post.vignette, "Vignette");
+            ui.end_row();
+            ui.label("Strength");
+            ui.add_enabled(
+                post.vignette,
+                egui::Slider::new(&mut post.vignette_strength, 0.0..=1.0),
+            );
+            ui.end_row();
+        });
+}
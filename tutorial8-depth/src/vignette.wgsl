@@ -0,0 +1,19 @@
+// Darkens the image towards the corners
+
+struct VignetteParams {
+    strength: f32,
+    // Distance from the center where the falloff starts
+    radius: f32,
+    softness: f32,
+}
+
+@group(1) @binding(0)
+var<uniform> params: VignetteParams;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(input_texture, input_sampler, in.uv);
+    let distance = length(in.uv - 0.5) * 1.41421356;
+    let falloff = smoothstep(params.radius, params.radius + params.softness, distance);
+    return vec4<f32>(color.rgb * (1.0 - falloff * params.strength), color.a);
+}